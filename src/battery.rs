@@ -4,10 +4,11 @@
 // Copied and edited by vncnz
 
 use std::sync::mpsc::Sender;
+use serde::Serialize;
 use colored::Colorize;
 
 // #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize_repr, Serialize_repr, OwnedValue)]
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 #[repr(u32)]
 pub enum BatteryState {
     Unknown = 0,
@@ -62,7 +63,7 @@ pub enum BatteryLevel {
     Full = 8,
 } */
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BatteryStats {
     pub state: BatteryState,
     pub percentage: f64,
@@ -0,0 +1,318 @@
+// Typed commands shared by the FIFO, the control socket and the demo thread.
+// Everything that reaches HeimdallrLayer from the outside goes through parse_command first.
+
+use std::time::Duration;
+
+use regex::Regex;
+
+use crate::events::EVENT_NAMES;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Shows the wob-like indicator; kind is e.g. "volume" or "brightness"
+    /// color overrides the color of the kind, e.g. for lines coming from the wob-compatible FIFO
+    Wob { value: f64, kind: Option<String>, muted: bool, color: Option<(f64, f64, f64, f64)> },
+    /// Short form "volume 0.35 [muted]"; only configured kinds are accepted, anything else is an unknown command
+    KindValue { kind: String, value: f64, muted: bool },
+    /// Timer/stopwatch control, same syntax accepted by Countdown::fill_from_timespan
+    Timer(String),
+    Notification(NotificationCommand),
+    Icon(IconCommand),
+    History(HistoryCommand),
+    /// Do-not-disturb: "on", "off", "toggle" or "auto"; None only reports the state
+    Dnd(Option<String>),
+    /// Returns a JSON snapshot of the HUD state
+    Query,
+    /// Turns a control socket connection into a stream of events (all of them if the list is empty)
+    Subscribe(Vec<String>),
+    /// Reloads the configuration file
+    Reload,
+    /// Exits after replying with the state a new instance needs to take over
+    Shutdown,
+    /// Fake events used while developing the UI
+    Demo { kind: String, value: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotificationCommand {
+    Dismiss,
+    /// Moves through the queue, wrapping around: 1 is the next one, -1 the previous one
+    Browse(isize),
+    /// 1-based position in the queue
+    Show(usize),
+    /// Back to normal mode, keeping the queue
+    Hide,
+    /// "default", a 1-based index among the shown actions, or an action key
+    Action(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IconCommand {
+    /// Icons set from outside can expire after ttl and carry a short description
+    Set { id: String, symbol: String, warn: f64, ttl: Option<Duration>, info: Option<String> },
+    Clear { id: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryCommand {
+    /// The last n entries, newest first
+    List(usize),
+    /// Case insensitive regex on app name, summary and body
+    Search(String),
+    /// Shows the entry with this number in the pill again
+    Show(u64),
+    Clear,
+}
+
+pub const HISTORY_LIST_DEFAULT: usize = 20;
+
+/// Splits a command line in words. Single and double quotes group words
+/// together, a backslash escapes the next char (except inside single quotes).
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => current.push(c),
+            (Some('"'), '"') => quote = None,
+            (_, '\\') => {
+                let escaped = chars.next().ok_or("Trailing backslash")?;
+                current.push(escaped);
+                in_token = true;
+            }
+            (Some(_), _) => current.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_token = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            (None, _) => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err("Unterminated quote".to_string());
+    }
+    if in_token {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+/// Inverse of tokenize for a single word: quotes it only when needed
+pub fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.chars().any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        return word.to_string();
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    // "nan" and "inf" parse as f64 but aren't values
+    value.parse::<f64>().ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("Invalid number: {}", value))
+}
+
+/// Parses durations like "1h30m", "10m" or "45s"
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let re = Regex::new(r"^(?:(?P<hours>\d+)h)?(?:(?P<mins>\d+)m)?(?:(?P<secs>\d+)s)?$").unwrap();
+    let caps = re.captures(value).ok_or_else(|| format!("Invalid duration: {}", value))?;
+    let field = |name: &str| caps.name(name).and_then(|m| m.as_str().parse::<u64>().ok()).unwrap_or(0);
+
+    let secs = field("hours") * 3600 + field("mins") * 60 + field("secs");
+    if secs == 0 {
+        return Err(format!("Invalid duration: {}", value));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+type Flags<'a> = Vec<(&'a str, &'a str)>;
+
+/// Splits "--flag value" pairs from positional words
+fn split_flags<'a>(words: &[&'a str], allowed: &[&str]) -> Result<(Vec<&'a str>, Flags<'a>), String> {
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut iter = words.iter();
+
+    while let Some(word) = iter.next() {
+        if let Some(flag) = word.strip_prefix("--") {
+            if !allowed.contains(&flag) {
+                return Err(format!("Unknown option: --{}", flag));
+            }
+            let value = iter.next().ok_or_else(|| format!("Missing value for --{}", flag))?;
+            flags.push((flag, *value));
+        } else {
+            positional.push(*word);
+        }
+    }
+    Ok((positional, flags))
+}
+
+fn parse_icon_set(args: &[&str]) -> Result<Command, String> {
+    let (positional, flags) = split_flags(args, &["ttl", "info"])?;
+    let [id, symbol, warn] = positional.as_slice() else {
+        return Err("Usage: icon set <id> <symbol> <warn> [--ttl <duration>] [--info <text>]".to_string());
+    };
+
+    let mut ttl = None;
+    let mut info = None;
+    for (flag, value) in flags {
+        match flag {
+            "ttl" => ttl = Some(parse_duration(value)?),
+            _ => info = Some(value.to_string()),
+        }
+    }
+
+    Ok(Command::Icon(IconCommand::Set {
+        id: id.to_string(),
+        symbol: symbol.to_string(),
+        warn: parse_number(warn)?.clamp(0.0, 1.0),
+        ttl,
+        info,
+    }))
+}
+
+/// Parses "#RRGGBB" or "#RRGGBBAA"
+pub fn parse_color(value: &str) -> Result<(f64, f64, f64, f64), String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid color: {} (expected #RRGGBB or #RRGGBBAA)", value));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map(|c| c as f64 / 255.0).unwrap_or(1.0);
+    let alpha = if hex.len() == 8 { channel(6) } else { 1.0 };
+    Ok((channel(0), channel(2), channel(4), alpha))
+}
+
+/// Inverse of parse_color
+pub fn format_color(color: (f64, f64, f64, f64)) -> String {
+    let byte = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02X}{:02X}{:02X}{:02X}", byte(color.0), byte(color.1), byte(color.2), byte(color.3))
+}
+
+/// Translates a line in wob's input format, "<value> [#BACKGROUND #BORDER #BAR]" with value between 0 and max,
+/// into a `wob` command. Only the bar color is used: background and border belong to the pill.
+pub fn wob_line_to_command(line: &str, max: u32, kind: Option<&str>) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (value, bar_color) = match words.as_slice() {
+        [value] => (value, None),
+        [value, background, border, bar] => {
+            parse_color(background)?;
+            parse_color(border)?;
+            (value, Some(parse_color(bar)?))
+        },
+        _ => return Err("Expected <value> [#BACKGROUND #BORDER #BAR]".to_string()),
+    };
+
+    let value: u32 = value.parse().map_err(|_| format!("Invalid value: {}", value))?;
+    if value > max {
+        return Err(format!("Value {} is greater than {}", value, max));
+    }
+
+    let mut cmd = format!("wob {}", value as f64 / max.max(1) as f64);
+    if let Some(kind) = kind { cmd += &format!(" {}", quote(kind)); }
+    if let Some(color) = bar_color { cmd += &format!(" --color {}", format_color(color)); }
+    Ok(cmd)
+}
+
+/// "muted" can follow the value, with or without a kind
+fn parse_wob(value: &str, rest: &[&str], color: Option<(f64, f64, f64, f64)>) -> Result<Command, String> {
+    let (kind, muted) = match rest {
+        [] => (None, false),
+        ["muted"] => (None, true),
+        [kind] => (Some(kind.to_string()), false),
+        [kind, "muted"] => (Some(kind.to_string()), true),
+        _ => return Err("Usage: wob <value> [kind] [muted] [--color #RRGGBB[AA]]".to_string()),
+    };
+    Ok(Command::Wob { value: parse_number(value)?, kind, muted, color })
+}
+
+fn parse_wob_command(args: &[&str]) -> Result<Command, String> {
+    let (positional, flags) = split_flags(args, &["color"])?;
+    let color = flags.last().map(|(_, value)| parse_color(value)).transpose()?;
+    match positional.as_slice() {
+        [value, rest @ ..] => parse_wob(value, rest, color),
+        [] => Err("Usage: wob <value> [kind] [muted] [--color #RRGGBB[AA]]".to_string()),
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let tokens = tokenize(line)?;
+    let words: Vec<&str> = tokens.iter().map(|t| t.as_str()).collect();
+
+    match words.as_slice() {
+        [] => Err("Empty command".to_string()),
+
+        ["wob", args @ ..] => parse_wob_command(args),
+
+        ["timer", spec] => Ok(Command::Timer(spec.to_string())),
+        ["timer", ..] => Err("Usage: timer <XXmYYs|up|p|r|off>".to_string()),
+
+        // Kept for compatibility with the first FIFO protocol
+        ["hide_notification"] => Ok(Command::Notification(NotificationCommand::Dismiss)),
+        ["notification", "dismiss"] => Ok(Command::Notification(NotificationCommand::Dismiss)),
+        ["notification", "action", which] => Ok(Command::Notification(NotificationCommand::Action(which.to_string()))),
+        ["notification", "next"] | ["next_notification"] => Ok(Command::Notification(NotificationCommand::Browse(1))),
+        ["notification", "prev"] | ["prev_notification"] => Ok(Command::Notification(NotificationCommand::Browse(-1))),
+        ["notification", "show", n] => n.parse()
+            .map(|n| Command::Notification(NotificationCommand::Show(n)))
+            .map_err(|_| format!("Invalid position: {n}")),
+        ["notification", "hide"] => Ok(Command::Notification(NotificationCommand::Hide)),
+        ["notification", ..] => Err("Usage: notification dismiss | notification action <default|n|key> | notification next | notification prev | notification show <n> | notification hide".to_string()),
+
+        ["icon", "set", args @ ..] => parse_icon_set(args),
+        ["icon", "clear", id] => Ok(Command::Icon(IconCommand::Clear { id: id.to_string() })),
+        ["icon", ..] => Err("Usage: icon set <id> <symbol> <warn> [--ttl <duration>] [--info <text>] | icon clear <id>".to_string()),
+
+        ["history", "list"] => Ok(Command::History(HistoryCommand::List(HISTORY_LIST_DEFAULT))),
+        ["history", "list", n] => n.parse()
+            .map(|n| Command::History(HistoryCommand::List(n)))
+            .map_err(|_| format!("Invalid count: {n}")),
+        ["history", "search", pattern] => Ok(Command::History(HistoryCommand::Search(pattern.to_string()))),
+        ["history", "show", seq] => seq.parse()
+            .map(|seq| Command::History(HistoryCommand::Show(seq)))
+            .map_err(|_| format!("Invalid history entry: {seq}")),
+        ["history", "clear"] => Ok(Command::History(HistoryCommand::Clear)),
+        ["history", ..] => Err("Usage: history list [n] | history search <regex> | history show <n> | history clear".to_string()),
+
+        ["dnd"] => Ok(Command::Dnd(None)),
+        ["dnd", mode @ ("on" | "off" | "toggle" | "auto")] => Ok(Command::Dnd(Some(mode.to_string()))),
+        ["dnd", ..] => Err("Usage: dnd [on|off|toggle|auto]".to_string()),
+
+        ["query"] => Ok(Command::Query),
+        ["subscribe", names @ ..] => {
+            if let Some(unknown) = names.iter().find(|n| !EVENT_NAMES.contains(n)) {
+                return Err(format!("Unknown event: {} (available: {})", unknown, EVENT_NAMES.join(", ")));
+            }
+            Ok(Command::Subscribe(names.iter().map(|n| n.to_string()).collect()))
+        },
+        ["reload"] => Ok(Command::Reload),
+        ["shutdown"] => Ok(Command::Shutdown),
+
+        ["demo", kind, rest @ ..] if !rest.is_empty() => Ok(Command::Demo { kind: kind.to_string(), value: rest.join(" ") }),
+
+        // Bare values: "0.35" or "volume 0.35"
+        [value] => parse_number(value)
+            .map(|value| Command::Wob { value, kind: None, muted: false, color: None })
+            .map_err(|_| format!("Unknown command: {}", value)),
+        [kind, value, rest @ ..] if parse_number(value).is_ok() => match rest {
+            [] | ["muted"] => Ok(Command::KindValue { kind: kind.to_string(), value: parse_number(value)?, muted: !rest.is_empty() }),
+            _ => Err("Usage: <kind> <value> [muted]".to_string()),
+        },
+
+        [verb, ..] => Err(format!("Unknown command: {}", verb)),
+    }
+}
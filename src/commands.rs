@@ -1,15 +1,24 @@
 use std::fs;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::sync::mpsc::Sender;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
 use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::command_parser::{Command, parse_command, wob_line_to_command};
+use crate::events::EventBus;
 
 use std::ffi::CString;
 
 use libc;
 
-fn create_fifo(path: &str) -> std::io::Result<()> {
-    let c_path = CString::new(path).unwrap();
+fn create_fifo(path: &Path) -> std::io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
     let mode = 0o600; // rw------- (solo utente)
     let ret = unsafe { libc::mkfifo(c_path.as_ptr(), mode as libc::mode_t) };
     if ret == 0 {
@@ -19,8 +28,57 @@ fn create_fifo(path: &str) -> std::io::Result<()> {
     }
 }
 
-pub fn start_command_listener(tx: Sender<String>, fifo_path: &str) -> std::io::Result<()> {
-    let path = Path::new(fifo_path);
+/// How long a socket client waits for the main loop to handle its request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A command line received from the FIFO or the control socket.
+/// FIFO commands have no reply channel: nobody is listening on the other side.
+pub struct CommandRequest {
+    pub line: String,
+    pub reply: Option<Sender<CommandReply>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandReply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl CommandReply {
+    pub fn ok(data: Option<serde_json::Value>) -> Self {
+        CommandReply { ok: true, error: None, data }
+    }
+
+    pub fn error(msg: impl Into<String>) -> Self {
+        CommandReply { ok: false, error: Some(msg.into()), data: None }
+    }
+
+    pub fn from_result(result: Result<Option<serde_json::Value>, String>) -> Self {
+        match result {
+            Ok(data) => CommandReply::ok(data),
+            Err(e) => CommandReply::error(e),
+        }
+    }
+}
+
+pub fn start_command_listener(tx: Sender<CommandRequest>, path: &Path) -> std::io::Result<()> {
+    start_fifo_listener(tx, path, |line| Ok(line.to_string()))
+}
+
+/// FIFO speaking wob's input format, so scripts written for wob can feed heimdallr unchanged.
+/// `max` is the value that fills the bar, like wob's --max.
+pub fn start_wob_listener(tx: Sender<CommandRequest>, path: &Path, max: u32, kind: Option<String>) -> std::io::Result<()> {
+    start_fifo_listener(tx, path, move |line| wob_line_to_command(line, max, kind.as_deref()))
+}
+
+/// Every line read from the FIFO goes through translate before becoming a command
+fn start_fifo_listener<F>(tx: Sender<CommandRequest>, path: &Path, translate: F) -> std::io::Result<()>
+where
+    F: Fn(&str) -> Result<String, String> + Send + 'static,
+{
 
     // Se esiste un file precedente (vecchia FIFO o file normale), lo rimuoviamo
     if path.exists() {
@@ -28,11 +86,11 @@ pub fn start_command_listener(tx: Sender<String>, fifo_path: &str) -> std::io::R
     }
 
     // Creiamo la FIFO (named pipe)
-    create_fifo(fifo_path)?;
-    println!("Created FIFO for commands receiving, located in {}", fifo_path);
+    create_fifo(path)?;
+    println!("Created FIFO for commands receiving, located in {}", path.display());
 
     // Lanciamo un thread dedicato alla lettura
-    let path_owned = fifo_path.to_string();
+    let path_owned: PathBuf = path.to_path_buf();
     thread::spawn(move || {
         loop {
             // Apriamo la FIFO in sola lettura (bloccante finché qualcuno scrive)
@@ -50,7 +108,14 @@ pub fn start_command_listener(tx: Sender<String>, fifo_path: &str) -> std::io::R
             for line in reader.lines() {
                 match line {
                     Ok(cmd) if !cmd.trim().is_empty() => {
-                        if let Err(e) = tx.send(cmd.clone()) {
+                        let cmd = match translate(cmd.trim()) {
+                            Ok(cmd) => cmd,
+                            Err(e) => {
+                                eprintln!("Invalid line '{cmd}' in {}: {e}", path_owned.display());
+                                continue;
+                            }
+                        };
+                        if let Err(e) = tx.send(CommandRequest { line: cmd.clone(), reply: None }) {
                             eprintln!("Failed to send command '{cmd}': {e}");
                             break;
                         }
@@ -66,3 +131,84 @@ pub fn start_command_listener(tx: Sender<String>, fifo_path: &str) -> std::io::R
 
     Ok(())
 }
+
+/// Listens on a Unix domain socket: every line is a command, every command gets
+/// exactly one JSON line back, e.g. {"ok":true,"data":{...}} or {"ok":false,"error":"..."}
+/// A `subscribe` line turns the connection into a one-way stream of events.
+pub fn start_socket_listener(tx: Sender<CommandRequest>, path: &Path, events: EventBus) -> std::io::Result<()> {
+
+    // A stale socket from a previous run would make bind() fail
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    println!("Control socket listening on {}", path.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    let events = events.clone();
+                    thread::spawn(move || handle_socket_client(stream, tx, events));
+                }
+                Err(e) => eprintln!("Control socket accept error: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn write_reply(writer: &mut UnixStream, reply: &CommandReply) -> std::io::Result<()> {
+    let mut out = serde_json::to_string(reply).unwrap_or_else(|_| r#"{"ok":false,"error":"Cannot serialize reply"}"#.to_string());
+    out.push('\n');
+    writer.write_all(out.as_bytes())
+}
+
+fn handle_socket_client(stream: UnixStream, tx: Sender<CommandRequest>, events: EventBus) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("Control socket clone error: {e}");
+            return;
+        }
+    };
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Control socket read error: {e}");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Subscriptions don't involve the main loop: from now on the main loop writes here
+        if let Ok(Command::Subscribe(filter)) = parse_command(&line) {
+            if write_reply(&mut writer, &CommandReply::ok(None)).is_ok() {
+                events.subscribe(writer, filter);
+            }
+            return;
+        }
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let reply = if tx.send(CommandRequest { line, reply: Some(reply_tx) }).is_err() {
+            CommandReply::error("Daemon is shutting down")
+        } else {
+            reply_rx
+                .recv_timeout(REPLY_TIMEOUT)
+                .unwrap_or_else(|_| CommandReply::error("Timed out waiting for the daemon"))
+        };
+
+        if write_reply(&mut writer, &reply).is_err() {
+            break; // client went away
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use std::collections::HashMap;
 use std::fs;
+use std::time::Duration;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
 use rand::Rng;
+use regex::{Regex, RegexBuilder};
 use serde::Deserialize;
 
 #[derive(Debug, Clone)]
@@ -51,6 +55,230 @@ pub enum LayerBackend {
     }
 } */
 
+/// Overrides for the files heimdallr creates, see paths.rs for the defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PathsConfig {
+    pub command_fifo: Option<String>,
+    pub control_socket: Option<String>,
+    pub ratatoskr_socket: Option<String>,
+    pub log_file: Option<String>,
+    /// Created only when set, see WobConfig::compat_max
+    pub wob_fifo: Option<String>,
+}
+
+/// How values of a given kind ("volume", "brightness"...) are shown by the wob-like indicator
+#[derive(Debug, Clone)]
+pub struct WobKind {
+    pub color: (f64, f64, f64, f64),
+    /// Chosen by level with select_icon, from lowest to highest
+    pub icons: Vec<String>,
+    pub muted_icon: Option<String>,
+    /// Highest accepted value, e.g. 1.5 for a volume boosted to 150%
+    pub max: f64,
+    /// Color of the bar past 100%
+    pub over_color: (f64, f64, f64, f64),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawWobKind {
+    color: Option<(f64, f64, f64, f64)>,
+    icons: Option<Vec<String>>,
+    muted_icon: Option<String>,
+    max: Option<f64>,
+    over_color: Option<(f64, f64, f64, f64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WobConfig {
+    pub timeout: Duration,
+    /// Used for values without a kind or with a kind missing from kinds
+    pub default_kind: WobKind,
+    pub kinds: HashMap<String, WobKind>,
+    /// Value filling the bar for lines read from the wob-compatible FIFO, like wob's --max
+    pub compat_max: u32,
+    /// Kind given to values read from the wob-compatible FIFO
+    pub compat_kind: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawWobConfig {
+    timeout_ms: Option<u64>,
+    kinds: Option<HashMap<String, RawWobKind>>,
+    compat_max: Option<u32>,
+    compat_kind: Option<String>,
+}
+
+/// Retention of the notification history, see history.rs
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub max_entries: usize,
+    /// None keeps entries until max_entries pushes them out
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawHistoryConfig {
+    enabled: Option<bool>,
+    max_entries: Option<usize>,
+    /// 0 disables the age limit
+    max_age_days: Option<u64>,
+}
+
+/// Collapsing of notifications that follow each other from the same app
+#[derive(Debug, Clone)]
+pub struct GroupingConfig {
+    pub enabled: bool,
+    /// Only collapse notifications that also share the summary (e.g. the same chat)
+    pub same_summary: bool,
+    /// Identical notifications (app, summary and body) within this window are dropped, None keeps them
+    pub dedupe_window: Option<Duration>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawGroupingConfig {
+    enabled: Option<bool>,
+    same_summary: Option<bool>,
+    /// 0 disables deduplication
+    dedupe_window_ms: Option<u64>,
+}
+
+/// Limits on what a single app can push, so a misbehaving one can't bury the HUD
+#[derive(Debug, Clone)]
+pub struct FloodConfig {
+    /// Notifications each app can send per second once its burst is spent, 0 disables the limit
+    pub per_second: f64,
+    /// Notifications each app can send at once
+    pub burst: f64,
+    /// Notifications in the queue, the oldest ones go beyond this
+    pub max_queue: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawFloodConfig {
+    per_second: Option<f64>,
+    burst: Option<u32>,
+    max_queue: Option<usize>,
+}
+
+/// One line (or part of the first line) of a notification in the pill
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutField {
+    pub show: bool,
+    pub font_size: f64,
+    /// Longer text is ellipsized, 0 for no limit
+    pub max_lines: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawLayoutField {
+    show: Option<bool>,
+    font_size: Option<f64>,
+    max_lines: Option<u32>,
+}
+
+/// What a notification shows: the first line has app name, time and age, then summary and body
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationLayout {
+    /// Text wider than this wraps
+    pub max_width: f64,
+    pub app_name: LayoutField,
+    pub summary: LayoutField,
+    pub body: LayoutField,
+    /// When the notification was received, formatted with time_format
+    pub time: LayoutField,
+    pub time_format: String,
+    /// "5 min ago"
+    pub age: LayoutField,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawNotificationLayout {
+    max_width: Option<f64>,
+    app_name: Option<RawLayoutField>,
+    summary: Option<RawLayoutField>,
+    body: Option<RawLayoutField>,
+    time: Option<RawLayoutField>,
+    time_format: Option<String>,
+    age: Option<RawLayoutField>,
+}
+
+/// A daily do-not-disturb window; from > to spans midnight, from == to lasts the whole day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DndSchedule {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+    /// Days the window starts on, empty means every day
+    pub days: Vec<Weekday>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawDndSchedule {
+    from: String,
+    to: String,
+    days: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DndConfig {
+    pub schedules: Vec<DndSchedule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawDndConfig {
+    schedules: Option<Vec<RawDndSchedule>>,
+}
+
+/// What a matching rule does to a notification
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleEffects {
+    pub drop: bool,
+    /// Recorded in the history but never shown, even without do-not-disturb
+    pub history_only: bool,
+    /// Some(None) never expires
+    pub timeout: Option<Option<Duration>>,
+    pub urgency: Option<u8>,
+    pub app_name: Option<String>,
+    pub app_icon: Option<String>,
+    /// Color of the app name in the pill
+    pub color: Option<(f64, f64, f64, f64)>,
+}
+
+/// Empty conditions match everything; rules are applied in order and later ones win
+#[derive(Debug, Clone)]
+pub struct NotificationRule {
+    /// Case insensitive, whole name
+    pub app_name: Option<String>,
+    pub summary: Option<Regex>,
+    pub body: Option<Regex>,
+    pub urgency: Option<u8>,
+    pub effects: RuleEffects,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawRuleMatch {
+    app_name: Option<String>,
+    summary: Option<String>,
+    body: Option<String>,
+    urgency: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawNotificationRule {
+    #[serde(rename = "match", default)]
+    matches: RawRuleMatch,
+    #[serde(default)]
+    drop: bool,
+    #[serde(default)]
+    history_only: bool,
+    /// 0 never expires
+    timeout_ms: Option<u64>,
+    urgency: Option<serde_json::Value>,
+    app_name: Option<String>,
+    app_icon: Option<String>,
+    color: Option<(f64, f64, f64, f64)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub frame_color: FrameColor,
@@ -59,7 +287,17 @@ pub struct Config {
     // pub show_always_bluetooth: bool,
     pub hide_missing_ratatoskr: bool,
     // pub border_width: u32,
-    pub show_watts: bool
+    pub show_watts: bool,
+    /// Freedesktop icon theme for notification icons, hicolor is always the fallback
+    pub icon_theme: Option<String>,
+    pub paths: PathsConfig,
+    pub wob: WobConfig,
+    pub history: HistoryConfig,
+    pub dnd: DndConfig,
+    pub rules: Vec<NotificationRule>,
+    pub grouping: GroupingConfig,
+    pub flood: FloodConfig,
+    pub notification_layout: NotificationLayout,
 }
 
 #[derive(Debug, Deserialize)]
@@ -71,6 +309,15 @@ struct RawConfig {
     hide_missing_ratatoskr: Option<bool>,
     // border_width: Option<u32>,
     show_watts: Option<bool>,
+    icon_theme: Option<String>,
+    paths: Option<PathsConfig>,
+    wob: Option<RawWobConfig>,
+    history: Option<RawHistoryConfig>,
+    dnd: Option<RawDndConfig>,
+    rules: Option<Vec<RawNotificationRule>>,
+    grouping: Option<RawGroupingConfig>,
+    flood: Option<RawFloodConfig>,
+    notification_layout: Option<RawNotificationLayout>,
 }
 
 impl FrameColor {
@@ -140,6 +387,261 @@ impl FrameColor {
     }
 } */
 
+impl WobKind {
+    fn new(color: (f64, f64, f64, f64), icons: &[&str], muted_icon: Option<&str>) -> Self {
+        WobKind {
+            color,
+            icons: icons.iter().map(|i| i.to_string()).collect(),
+            muted_icon: muted_icon.map(|i| i.to_string()),
+            max: 1.0,
+            over_color: (1.0, 0.58, 0.0, 1.0),
+        }
+    }
+
+    fn with_max(mut self, max: f64) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Fields missing in the config file keep the built-in value
+    fn merge(mut self, raw: RawWobKind) -> Self {
+        if let Some(color) = raw.color { self.color = color; }
+        if let Some(icons) = raw.icons { self.icons = icons; }
+        if raw.muted_icon.is_some() { self.muted_icon = raw.muted_icon; }
+        // Below 1.0 the bar could never be full
+        if let Some(max) = raw.max { self.max = max.max(1.0); }
+        if let Some(over_color) = raw.over_color { self.over_color = over_color; }
+        self
+    }
+}
+
+impl WobConfig {
+    fn from_raw(raw: Option<RawWobConfig>) -> Self {
+        let raw = raw.unwrap_or_default();
+        let mut kinds = HashMap::from([
+            ("volume".to_string(), WobKind::new((0.35, 0.75, 0.45, 1.0), &["󰕿", "󰖀", "󰕾"], Some("󰝟")).with_max(1.5)),
+            ("brightness".to_string(), WobKind::new((0.95, 0.8, 0.3, 1.0), &["󰃞", "󰃟", "󰃠"], None)),
+            ("keyboard".to_string(), WobKind::new((0.55, 0.7, 0.95, 1.0), &["󰌌"], None)),
+            ("microphone".to_string(), WobKind::new((0.85, 0.5, 0.85, 1.0), &["󰍬"], Some("󰍭"))),
+        ]);
+        let mut default_kind = WobKind::new((0.6, 0.6, 0.7, 1.0), &[], None);
+
+        for (name, raw_kind) in raw.kinds.unwrap_or_default() {
+            if name == "default" {
+                default_kind = default_kind.merge(raw_kind);
+            } else {
+                let base = kinds.remove(&name).unwrap_or_else(|| default_kind.clone());
+                kinds.insert(name, base.merge(raw_kind));
+            }
+        }
+
+        WobConfig {
+            timeout: Duration::from_millis(raw.timeout_ms.unwrap_or(2000)),
+            default_kind,
+            kinds,
+            compat_max: raw.compat_max.filter(|m| *m > 0).unwrap_or(100),
+            compat_kind: raw.compat_kind,
+        }
+    }
+
+    pub fn kind(&self, name: Option<&str>) -> &WobKind {
+        name.and_then(|n| self.kinds.get(n)).unwrap_or(&self.default_kind)
+    }
+}
+
+impl HistoryConfig {
+    fn from_raw(raw: Option<RawHistoryConfig>) -> Self {
+        let raw = raw.unwrap_or_default();
+        HistoryConfig {
+            enabled: raw.enabled.unwrap_or(true),
+            max_entries: raw.max_entries.unwrap_or(200),
+            max_age: match raw.max_age_days.unwrap_or(30) {
+                0 => None,
+                days => Some(Duration::from_secs(days * 24 * 60 * 60)),
+            },
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig::from_raw(None)
+    }
+}
+
+impl GroupingConfig {
+    fn from_raw(raw: Option<RawGroupingConfig>) -> Self {
+        let raw = raw.unwrap_or_default();
+        GroupingConfig {
+            enabled: raw.enabled.unwrap_or(true),
+            same_summary: raw.same_summary.unwrap_or(false),
+            dedupe_window: match raw.dedupe_window_ms.unwrap_or(10_000) {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
+        }
+    }
+}
+
+impl FloodConfig {
+    fn from_raw(raw: Option<RawFloodConfig>) -> Self {
+        let raw = raw.unwrap_or_default();
+        FloodConfig {
+            per_second: raw.per_second.filter(|r| r.is_finite()).unwrap_or(1.0).max(0.0),
+            burst: raw.burst.unwrap_or(5).max(1) as f64,
+            max_queue: raw.max_queue.unwrap_or(50).max(1),
+        }
+    }
+}
+
+impl LayoutField {
+    /// Missing values come from `default`
+    fn from_raw(raw: Option<RawLayoutField>, default: LayoutField) -> Self {
+        let raw = raw.unwrap_or_default();
+        LayoutField {
+            show: raw.show.unwrap_or(default.show),
+            font_size: raw.font_size.filter(|s| *s > 0.0).unwrap_or(default.font_size),
+            max_lines: raw.max_lines.unwrap_or(default.max_lines),
+        }
+    }
+}
+
+impl NotificationLayout {
+    fn from_raw(raw: Option<RawNotificationLayout>) -> Self {
+        let raw = raw.unwrap_or_default();
+        let field = |show: bool, font_size: f64, max_lines: u32| LayoutField { show, font_size, max_lines };
+        NotificationLayout {
+            max_width: raw.max_width.filter(|w| *w > 0.0).unwrap_or(500.0),
+            app_name: LayoutField::from_raw(raw.app_name, field(true, 11.0, 1)),
+            summary: LayoutField::from_raw(raw.summary, field(true, 14.0, 2)),
+            body: LayoutField::from_raw(raw.body, field(true, 14.0, 0)),
+            time: LayoutField::from_raw(raw.time, field(false, 11.0, 1)),
+            // chrono panics when it meets an invalid specifier while formatting
+            time_format: raw.time_format
+                .filter(|f| {
+                    let valid = !chrono::format::StrftimeItems::new(f).any(|item| item == chrono::format::Item::Error);
+                    if !valid {
+                        eprintln!("Invalid time_format {f:?} in notification_layout, using %H:%M");
+                    }
+                    valid
+                })
+                .unwrap_or_else(|| "%H:%M".to_string()),
+            age: LayoutField::from_raw(raw.age, field(false, 11.0, 1)),
+        }
+    }
+}
+
+impl DndSchedule {
+    fn from_raw(raw: RawDndSchedule) -> Result<Self, String> {
+        let time = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").map_err(|_| format!("Invalid time {s:?}, expected HH:MM"));
+        let days = raw.days.unwrap_or_default()
+            .iter()
+            .map(|d| d.parse::<Weekday>().map_err(|_| format!("Invalid day {d:?}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DndSchedule { from: time(&raw.from)?, to: time(&raw.to)?, days })
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let (time, today) = (now.time(), now.weekday());
+        if self.from < self.to {
+            self.starts_on(today) && self.from <= time && time < self.to
+        } else if self.from > self.to {
+            // The part after midnight belongs to the window started yesterday
+            (self.starts_on(today) && time >= self.from) || (self.starts_on(today.pred()) && time < self.to)
+        } else {
+            self.starts_on(today)
+        }
+    }
+}
+
+impl DndConfig {
+    fn from_raw(raw: Option<RawDndConfig>) -> Self {
+        let schedules = raw.unwrap_or_default().schedules.unwrap_or_default()
+            .into_iter()
+            .filter_map(|raw| DndSchedule::from_raw(raw)
+                .inspect_err(|e| eprintln!("Ignoring do-not-disturb schedule: {e}"))
+                .ok())
+            .collect();
+        DndConfig { schedules }
+    }
+
+    pub fn scheduled(&self, now: NaiveDateTime) -> bool {
+        self.schedules.iter().any(|s| s.contains(now))
+    }
+}
+
+/// "low", "normal", "critical" or 0, 1, 2 like the urgency hint
+fn parse_urgency(value: &serde_json::Value) -> Result<u8, String> {
+    match value {
+        serde_json::Value::String(s) if s == "low" => Ok(0),
+        serde_json::Value::String(s) if s == "normal" => Ok(1),
+        serde_json::Value::String(s) if s == "critical" => Ok(2),
+        serde_json::Value::Number(n) if n.as_u64().is_some_and(|n| n <= 2) => Ok(n.as_u64().unwrap_or(1) as u8),
+        _ => Err(format!("Invalid urgency {value}, expected low, normal, critical or 0-2")),
+    }
+}
+
+impl NotificationRule {
+    fn from_raw(raw: RawNotificationRule) -> Result<Self, String> {
+        let regex = |pattern: Option<String>| pattern
+            .map(|p| RegexBuilder::new(&p).case_insensitive(true).build().map_err(|e| format!("Invalid pattern {p:?}: {e}")))
+            .transpose();
+
+        Ok(NotificationRule {
+            app_name: raw.matches.app_name,
+            summary: regex(raw.matches.summary)?,
+            body: regex(raw.matches.body)?,
+            urgency: raw.matches.urgency.as_ref().map(parse_urgency).transpose()?,
+            effects: RuleEffects {
+                drop: raw.drop,
+                history_only: raw.history_only,
+                timeout: raw.timeout_ms.map(|ms| (ms > 0).then(|| Duration::from_millis(ms))),
+                urgency: raw.urgency.as_ref().map(parse_urgency).transpose()?,
+                app_name: raw.app_name,
+                app_icon: raw.app_icon,
+                color: raw.color,
+            },
+        })
+    }
+
+    /// body is plain text, see markup::strip
+    pub fn matches(&self, app_name: &str, summary: &str, body: &str, urgency: u8) -> bool {
+        self.app_name.as_ref().is_none_or(|name| name.eq_ignore_ascii_case(app_name))
+            && self.summary.as_ref().is_none_or(|re| re.is_match(summary))
+            && self.body.as_ref().is_none_or(|re| re.is_match(body))
+            && self.urgency.is_none_or(|u| u == urgency)
+    }
+}
+
+fn rules_from_raw(raw: Option<Vec<RawNotificationRule>>) -> Vec<NotificationRule> {
+    raw.unwrap_or_default()
+        .into_iter()
+        .filter_map(|raw| NotificationRule::from_raw(raw)
+            .inspect_err(|e| eprintln!("Ignoring notification rule: {e}"))
+            .ok())
+        .collect()
+}
+
+impl PathsConfig {
+    /// Reads only the "paths" section, without complaining about a missing file: used by `heimdallr ctl`
+    pub fn load_from_file(path: &str) -> Self {
+        #[derive(Deserialize)]
+        struct OnlyPaths {
+            paths: Option<PathsConfig>,
+        }
+
+        fs::read_to_string(shellexpand::tilde(path).as_ref())
+            .ok()
+            .and_then(|data| serde_json::from_str::<OnlyPaths>(&data).ok())
+            .and_then(|raw| raw.paths)
+            .unwrap_or_default()
+    }
+}
+
 impl Config {
     pub fn load_from_file(path: &str) -> Self {
         let expanded_path = shellexpand::tilde(path);
@@ -157,7 +659,16 @@ impl Config {
                 // backend: None,
                 // show_always_bluetooth: None,
                 hide_missing_ratatoskr: None,
-                show_watts: None
+                show_watts: None,
+                icon_theme: None,
+                paths: None,
+                wob: None,
+                history: None,
+                dnd: None,
+                rules: None,
+                grouping: None,
+                flood: None,
+                notification_layout: None,
                 // border_width: None,
             }
         });
@@ -168,7 +679,16 @@ impl Config {
             // backend: LayerBackend::from_json(raw.backend),
             // show_always_bluetooth: raw.show_always_bluetooth.unwrap_or(true),
             hide_missing_ratatoskr: raw.hide_missing_ratatoskr.unwrap_or(false),
-            show_watts: raw.show_watts.unwrap_or(false)
+            show_watts: raw.show_watts.unwrap_or(false),
+            icon_theme: raw.icon_theme,
+            paths: raw.paths.unwrap_or_default(),
+            wob: WobConfig::from_raw(raw.wob),
+            history: HistoryConfig::from_raw(raw.history),
+            dnd: DndConfig::from_raw(raw.dnd),
+            rules: rules_from_raw(raw.rules),
+            grouping: GroupingConfig::from_raw(raw.grouping),
+            flood: FloodConfig::from_raw(raw.flood),
+            notification_layout: NotificationLayout::from_raw(raw.notification_layout),
             // border_width: raw.border_width.unwrap_or(2),
         }
     }
@@ -1,7 +1,11 @@
 use std::time::{Duration, Instant};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone)]
+use crate::handover::{TimerHandover, instant_ago};
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CountdownDirection {
     Up,
     Down
@@ -29,6 +33,50 @@ impl Countdown {
         self.state.is_some()
     }
 
+    /// Timer state as reported by the `query` command
+    pub fn snapshot(&self) -> serde_json::Value {
+        let Some((start, total_duration)) = self.state else {
+            return serde_json::json!({ "active": false });
+        };
+        let active_pause = self.current_pause_start.map(|t| t.elapsed()).unwrap_or(Duration::ZERO);
+        let elapsed_effective = start.elapsed().saturating_sub(self.total_paused_time + active_pause);
+        let (expired, _) = self.time_remaining();
+
+        serde_json::json!({
+            "active": true,
+            "direction": self.direction,
+            "paused": self.current_pause_start.is_some(),
+            "expired": expired,
+            "duration_secs": total_duration.as_secs(),
+            "elapsed_secs": elapsed_effective.as_secs(),
+            "remaining_secs": total_duration.saturating_sub(elapsed_effective).as_secs(),
+            "text": self.format_custom_duration().1
+        })
+    }
+
+    /// Timer state handed to the instance started with --replace
+    pub fn handover(&self) -> Option<TimerHandover> {
+        let (start, duration) = self.state?;
+        let active_pause = self.current_pause_start.map(|t| t.elapsed()).unwrap_or(Duration::ZERO);
+        let elapsed = start.elapsed().saturating_sub(self.total_paused_time + active_pause);
+
+        Some(TimerHandover {
+            direction: self.direction.clone(),
+            duration_ms: duration.as_millis() as u64,
+            elapsed_ms: elapsed.as_millis() as u64,
+            paused: self.current_pause_start.is_some(),
+        })
+    }
+
+    /// Inverse of handover: the timer continues from where the previous instance left it
+    pub fn restore(&mut self, timer: &TimerHandover) {
+        let now = Instant::now();
+        self.state = Some((instant_ago(Duration::from_millis(timer.elapsed_ms)), Duration::from_millis(timer.duration_ms)));
+        self.total_paused_time = Duration::ZERO;
+        self.current_pause_start = timer.paused.then_some(now);
+        self.direction = timer.direction.clone();
+    }
+
     /// Parses a timespan string like "10m30s" or "45s" and fills the timing property
     pub fn fill_from_timespan(&mut self, input: &str) -> Result<u64, &'static str> {
         if input.trim().is_empty() || input.trim() == "0" || input.trim() == "off" {
@@ -0,0 +1,234 @@
+// `heimdallr ctl ...`: a tiny client for the control socket of the running instance
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::{Args, Subcommand};
+use colored::Colorize;
+
+use crate::command_parser::quote;
+use crate::commands::CommandReply;
+
+/// Exit code used when the daemon answers with an error
+const EXIT_COMMAND_ERROR: i32 = 1;
+/// Exit code used when the daemon cannot be reached or does not answer in time
+const EXIT_CONNECTION_ERROR: i32 = 2;
+
+#[derive(Debug, Args)]
+pub struct CtlArgs {
+    #[arg(long, default_value_t = 2000, help = "Milliseconds to wait for the daemon")]
+    timeout: u64,
+
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum CtlCommand {
+    /// Show a value in the wob-like indicator
+    Wob {
+        value: f64,
+        #[arg(short, long)]
+        kind: Option<String>,
+        /// Show the value as muted
+        #[arg(short, long)]
+        muted: bool,
+        /// Bar color, #RRGGBB or #RRGGBBAA
+        #[arg(short, long)]
+        color: Option<String>,
+    },
+    /// Set, pause (p), resume (r) or remove (off) the timer, or start a stopwatch (up)
+    Timer { spec: String },
+    /// Control notifications
+    #[command(subcommand)]
+    Notification(NotificationAction),
+    /// Add or remove warning icons
+    #[command(subcommand)]
+    Icon(IconAction),
+    /// Browse the notifications received so far
+    #[command(subcommand)]
+    History(HistoryAction),
+    /// Print or change the do-not-disturb state: "auto" goes back to the schedule
+    Dnd {
+        #[arg(value_parser = ["on", "off", "toggle", "auto"])]
+        mode: Option<String>,
+    },
+    /// Print a JSON snapshot of what heimdallr is showing
+    Query,
+    /// Reload the configuration file
+    Reload,
+    /// Stop the running instance, printing the state it was showing
+    Shutdown,
+    /// Print events as JSON lines until interrupted, optionally only the given ones
+    Subscribe { events: Vec<String> },
+    /// Send a raw command, one argument per word, e.g. `heimdallr ctl raw timer 10m`
+    Raw { line: Vec<String> },
+}
+
+#[derive(Debug, Subcommand)]
+enum NotificationAction {
+    /// Hide the notification currently shown
+    Dismiss,
+    /// Invoke an action of the notification currently shown: "default", its number or its key
+    Action { action: String },
+    /// Show the next notification of the queue
+    Next,
+    /// Show the previous notification of the queue
+    Prev,
+    /// Show the notification at this position of the queue, starting from 1
+    Show { position: usize },
+    /// Go back to the normal pill, keeping the queue
+    Hide,
+}
+
+#[derive(Debug, Subcommand)]
+enum HistoryAction {
+    /// Print the last entries as JSON, newest first
+    List { count: Option<usize> },
+    /// Print the entries whose app name, summary or body match a regex (case insensitive)
+    Search { pattern: String },
+    /// Show an entry in the pill again, by its "seq" number
+    Show { seq: u64 },
+    /// Forget every entry
+    Clear,
+}
+
+#[derive(Debug, Subcommand)]
+enum IconAction {
+    /// Add or update a warning icon
+    Set {
+        id: String,
+        symbol: String,
+        warn: f64,
+        /// Remove the icon after this time, e.g. 30s, 10m, 1h30m
+        #[arg(long)]
+        ttl: Option<String>,
+        /// Short description shown by `query`
+        #[arg(long)]
+        info: Option<String>,
+    },
+    /// Remove a warning icon
+    Clear { id: String },
+}
+
+impl CtlCommand {
+    fn to_line(&self) -> String {
+        match self {
+            CtlCommand::Wob { value, kind, muted, color } => {
+                let mut line = format!("wob {value}");
+                if let Some(kind) = kind { line += &format!(" {}", quote(kind)); }
+                if *muted { line += " muted"; }
+                if let Some(color) = color { line += &format!(" --color {}", quote(color)); }
+                line
+            },
+            CtlCommand::Timer { spec } => format!("timer {}", quote(spec)),
+            CtlCommand::Notification(NotificationAction::Dismiss) => "notification dismiss".to_string(),
+            CtlCommand::Notification(NotificationAction::Action { action }) => format!("notification action {}", quote(action)),
+            CtlCommand::Notification(NotificationAction::Next) => "notification next".to_string(),
+            CtlCommand::Notification(NotificationAction::Prev) => "notification prev".to_string(),
+            CtlCommand::Notification(NotificationAction::Show { position }) => format!("notification show {position}"),
+            CtlCommand::Notification(NotificationAction::Hide) => "notification hide".to_string(),
+            CtlCommand::Icon(IconAction::Set { id, symbol, warn, ttl, info }) => {
+                let mut line = format!("icon set {} {} {warn}", quote(id), quote(symbol));
+                if let Some(ttl) = ttl { line += &format!(" --ttl {}", quote(ttl)); }
+                if let Some(info) = info { line += &format!(" --info {}", quote(info)); }
+                line
+            },
+            CtlCommand::Icon(IconAction::Clear { id }) => format!("icon clear {}", quote(id)),
+            CtlCommand::History(HistoryAction::List { count: None }) => "history list".to_string(),
+            CtlCommand::History(HistoryAction::List { count: Some(count) }) => format!("history list {count}"),
+            CtlCommand::History(HistoryAction::Search { pattern }) => format!("history search {}", quote(pattern)),
+            CtlCommand::History(HistoryAction::Show { seq }) => format!("history show {seq}"),
+            CtlCommand::History(HistoryAction::Clear) => "history clear".to_string(),
+            CtlCommand::Dnd { mode: None } => "dnd".to_string(),
+            CtlCommand::Dnd { mode: Some(mode) } => format!("dnd {mode}"),
+            CtlCommand::Query => "query".to_string(),
+            CtlCommand::Reload => "reload".to_string(),
+            CtlCommand::Shutdown => "shutdown".to_string(),
+            CtlCommand::Subscribe { events } => format!("subscribe {}", events.join(" ")),
+            CtlCommand::Raw { line } => line.iter().map(|word| quote(word)).collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+fn request(socket_path: &Path, line: &str, timeout: Duration) -> Result<(CommandReply, BufReader<UnixStream>), String> {
+    let mut stream = UnixStream::connect(socket_path)
+        .map_err(|e| format!("Cannot connect to {}, is heimdallr running? ({e})", socket_path.display()))?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+
+    stream
+        .write_all(format!("{line}\n").as_bytes())
+        .map_err(|e| format!("Cannot send command: {e}"))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut reply = String::new();
+    reader.read_line(&mut reply).map_err(|e| match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => "Timed out waiting for heimdallr".to_string(),
+        _ => format!("Cannot read reply: {e}"),
+    })?;
+
+    let reply = serde_json::from_str(&reply).map_err(|e| format!("Invalid reply {:?}: {e}", reply.trim()))?;
+    Ok((reply, reader))
+}
+
+/// Sends a single line and waits for its reply
+pub fn send_command(socket_path: &Path, line: &str, timeout: Duration) -> Result<CommandReply, String> {
+    request(socket_path, line, timeout).map(|(reply, _)| reply)
+}
+
+/// Copies the event stream to stdout until the daemon goes away
+fn stream_events(socket_path: &Path, line: &str, timeout: Duration) -> i32 {
+    let (reply, mut reader) = match request(socket_path, line, timeout) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            return EXIT_CONNECTION_ERROR;
+        }
+    };
+    if !reply.ok {
+        eprintln!("{}", reply.error.unwrap_or_else(|| "Unknown error".to_string()).red());
+        return EXIT_COMMAND_ERROR;
+    }
+
+    // Events can be minutes apart
+    reader.get_ref().set_read_timeout(None).ok();
+    let mut stdout = std::io::stdout();
+    let mut event = String::new();
+    while matches!(reader.read_line(&mut event), Ok(n) if n > 0) {
+        if stdout.write_all(event.as_bytes()).and_then(|_| stdout.flush()).is_err() {
+            break;
+        }
+        event.clear();
+    }
+    0
+}
+
+/// Runs the ctl subcommand and returns the process exit code
+pub fn run(args: CtlArgs, socket_path: &Path) -> i32 {
+    let line = args.command.to_line();
+    let timeout = Duration::from_millis(args.timeout);
+
+    if let CtlCommand::Subscribe { .. } = args.command {
+        return stream_events(socket_path, &line, timeout);
+    }
+
+    match send_command(socket_path, &line, timeout) {
+        Ok(reply) if reply.ok => {
+            if let Some(data) = reply.data {
+                println!("{}", serde_json::to_string_pretty(&data).unwrap_or_default());
+            }
+            0
+        }
+        Ok(reply) => {
+            eprintln!("{}", reply.error.unwrap_or_else(|| "Unknown error".to_string()).red());
+            EXIT_COMMAND_ERROR
+        }
+        Err(e) => {
+            eprintln!("{}", e.red());
+            EXIT_CONNECTION_ERROR
+        }
+    }
+}
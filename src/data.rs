@@ -1,7 +1,8 @@
 use std::sync::mpsc::{Sender,Receiver,channel};
 use std::os::unix::net::UnixStream;
 use std::io::Read;
-use serde::Deserialize;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
 
 #[derive(Default, Deserialize)]
 pub struct BluetoothStats {
@@ -12,7 +13,7 @@ pub struct BluetoothStats {
     pub warn: f64
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct BatteryDevice {
     pub name: String,
     pub kind: UPowerDeviceKind,
@@ -23,7 +24,7 @@ pub struct BatteryDevice {
     // pub connected: bool
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum UPowerDeviceKind {
     Unknown,
     LinePower,
@@ -66,16 +67,17 @@ pub struct PartialMsg {
 
 pub struct RatatoskrSocket {
     stream: Option<UnixStream>,
-    path: &'static str,
+    /// Candidates tried in order, see RuntimePaths::ratatoskr_sockets
+    paths: Vec<PathBuf>,
     tx: Sender<PartialMsg>,
     pub rx: Receiver<PartialMsg>,
     recv_buf: String,
 }
 
 impl RatatoskrSocket {
-    pub fn new(path: &'static str) -> Self {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
         let (tx, rx) = channel();
-        Self { stream: None, path, tx, rx, recv_buf: "".to_string() }
+        Self { stream: None, paths, tx, rx, recv_buf: "".to_string() }
     }
 
     pub fn try_connect(&mut self) {
@@ -83,9 +85,9 @@ impl RatatoskrSocket {
             return;
         }
 
-        match UnixStream::connect(self.path) {
-            Ok(stream) => {
-                println!("Ratatoskr connected");
+        match self.paths.iter().find_map(|p| UnixStream::connect(p).ok().map(|s| (p, s))) {
+            Some((path, stream)) => {
+                println!("Ratatoskr connected on {}", path.display());
                 stream.set_nonblocking(true).ok();
                 self.stream = Some(stream);
                 let _ = self.tx.send(PartialMsg {
@@ -95,7 +97,7 @@ impl RatatoskrSocket {
                     data: None
                 });
             }
-            Err(_) => {
+            None => {
                 // Not connected, atm
             }
         }
@@ -153,7 +155,7 @@ impl RatatoskrSocket {
 /*
 Usage example:
 
-let mut sock = RatatoskrSocket::new("/tmp/ratatoskr.sock");
+let mut sock = RatatoskrSocket::new(paths.ratatoskr_sockets.clone());
 
 ... and in the main loop ...
 
@@ -175,7 +177,7 @@ pub enum IconChange {
     // Removed,
     None
 }
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct AlarmIcon {
     pub symbol: String,
     pub color: (f64, f64, f64, f64), // RGBA
@@ -0,0 +1,49 @@
+// Do-not-disturb: while active, non-critical notifications go to the history only.
+// Toggled by the `dnd` command or by the schedules in the config; a manual choice lasts until the schedule changes state.
+
+use chrono::NaiveDateTime;
+
+use crate::config::DndConfig;
+
+#[derive(Debug, Clone, Default)]
+pub struct DndState {
+    /// Set by the `dnd` command, wins over the schedule
+    pub manual: Option<bool>,
+    /// What the schedules say, as of the last update
+    pub scheduled: bool,
+}
+
+impl DndState {
+    pub fn is_active(&self) -> bool {
+        self.manual.unwrap_or(self.scheduled)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({ "active": self.is_active(), "manual": self.manual, "scheduled": self.scheduled })
+    }
+
+    /// Follows the schedules; returns true if the active state changed
+    pub fn update_schedule(&mut self, config: &DndConfig, now: NaiveDateTime) -> bool {
+        let was_active = self.is_active();
+        let scheduled = config.scheduled(now);
+        if scheduled != self.scheduled {
+            // "dnd off" during a meeting must not survive the next scheduled night, and vice versa
+            self.scheduled = scheduled;
+            self.manual = None;
+        }
+        was_active != self.is_active()
+    }
+
+    /// "on", "off", "toggle" or "auto" (back to the schedule); returns true if the active state changed
+    pub fn set(&mut self, mode: &str) -> Result<bool, String> {
+        let was_active = self.is_active();
+        self.manual = match mode {
+            "on" => Some(true),
+            "off" => Some(false),
+            "toggle" => Some(!was_active),
+            "auto" => None,
+            _ => return Err(format!("Unknown dnd mode: {mode} (on, off, toggle, auto)")),
+        };
+        Ok(was_active != self.is_active())
+    }
+}
@@ -0,0 +1,113 @@
+// Live stream of what heimdallr observes, for clients that sent `subscribe` on the control socket.
+// Each event is a JSON line like {"event":"timer_finished"}.
+
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::battery::BatteryStats;
+use crate::countdown::CountdownDirection;
+use crate::notifications::CloseReason;
+
+/// A slow subscriber must not stall the main loop
+const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    NotificationReceived { id: u32, app_name: String, summary: String, body: String, urgency: u8 },
+    NotificationClosed { id: u32, reason: CloseReason },
+    NotificationAction { id: u32, key: String },
+    TimerStarted { direction: CountdownDirection, seconds: u64 },
+    TimerFinished,
+    TimerStopped,
+    IconAdded { id: String, symbol: String, warn: f64 },
+    IconRemoved { id: String },
+    BatteryChanged { battery: Option<BatteryStats> },
+    SecurityChanged { mic_active: Vec<String>, camera_active: Vec<String> },
+    DndChanged { active: bool },
+}
+
+pub const EVENT_NAMES: &[&str] = &[
+    "notification_received",
+    "notification_closed",
+    "notification_action",
+    "timer_started",
+    "timer_finished",
+    "timer_stopped",
+    "icon_added",
+    "icon_removed",
+    "battery_changed",
+    "security_changed",
+    "dnd_changed",
+];
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::NotificationReceived { .. } => "notification_received",
+            Event::NotificationClosed { .. } => "notification_closed",
+            Event::NotificationAction { .. } => "notification_action",
+            Event::TimerStarted { .. } => "timer_started",
+            Event::TimerFinished => "timer_finished",
+            Event::TimerStopped => "timer_stopped",
+            Event::IconAdded { .. } => "icon_added",
+            Event::IconRemoved { .. } => "icon_removed",
+            Event::BatteryChanged { .. } => "battery_changed",
+            Event::SecurityChanged { .. } => "security_changed",
+            Event::DndChanged { .. } => "dnd_changed",
+        }
+    }
+}
+
+struct Subscriber {
+    stream: UnixStream,
+    /// Event names the client asked for, empty means everything
+    filter: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    pub fn subscribe(&self, stream: UnixStream, filter: Vec<String>) {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok();
+        if let Ok(mut subs) = self.subscribers.lock() {
+            subs.push(Subscriber { stream, filter });
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        let Ok(mut subs) = self.subscribers.lock() else { return; };
+        if subs.is_empty() {
+            return;
+        }
+
+        let mut line = match serde_json::to_string(&event) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Cannot serialize event {:?}: {e}", event);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let name = event.name();
+        // Clients that went away are dropped at the first failed write
+        subs.retain_mut(|sub| {
+            if !sub.filter.is_empty() && !sub.filter.iter().any(|f| f == name) {
+                return true;
+            }
+            sub.stream.write_all(line.as_bytes()).is_ok()
+        });
+    }
+}
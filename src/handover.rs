@@ -0,0 +1,128 @@
+// State passed from a running instance to the one started with --replace.
+// The old instance sends it as the reply to `shutdown`, the new one restores it before showing anything.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::countdown::CountdownDirection;
+use crate::notifications::Notification;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerHandover {
+    pub direction: CountdownDirection,
+    pub duration_ms: u64,
+    /// Time the timer actually ran, pauses excluded
+    pub elapsed_ms: u64,
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationHandover {
+    pub id: u32,
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    pub urgency: u8,
+    pub replaces_id: u32,
+    pub unmounting: bool,
+    pub unmounted: bool,
+    pub reboot: bool,
+    /// RFC 3339
+    pub received: String,
+    pub expires_in_ms: Option<u64>,
+    #[serde(default)]
+    pub actions: Vec<(String, String)>,
+    #[serde(default)]
+    pub color: Option<(f64, f64, f64, f64)>,
+    #[serde(default)]
+    pub image_path: String,
+    #[serde(default)]
+    pub progress: Option<u8>,
+    #[serde(default)]
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IconHandover {
+    pub id: String,
+    pub symbol: String,
+    pub warn: f64,
+    pub info: Option<String>,
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Handover {
+    pub timer: Option<TimerHandover>,
+    pub notifications: Vec<NotificationHandover>,
+    /// Only icons set through commands: Ratatoskr sends its own again once it reconnects
+    pub icons: Vec<IconHandover>,
+    /// Do-not-disturb set by command, None if it followed the schedule
+    #[serde(default)]
+    pub dnd: Option<bool>,
+}
+
+/// `Instant::now() - ago`, without panicking right after boot
+pub fn instant_ago(ago: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_sub(ago).unwrap_or(now)
+}
+
+impl From<&Notification> for NotificationHandover {
+    fn from(n: &Notification) -> Self {
+        NotificationHandover {
+            id: n.id,
+            app_name: n.app_name.clone(),
+            app_icon: n.app_icon.clone(),
+            summary: n.summary.clone(),
+            body: n.body.clone(),
+            urgency: n.urgency,
+            replaces_id: n.replaces_id,
+            unmounting: n.unmounting,
+            unmounted: n.unmounted,
+            reboot: n.reboot,
+            received: n.datetime.to_rfc3339(),
+            expires_in_ms: n.expired_at.map(|exp| exp.saturating_duration_since(Instant::now()).as_millis() as u64),
+            actions: n.actions.clone(),
+            color: n.color,
+            image_path: n.image_path.clone(),
+            progress: n.progress,
+            count: n.count,
+        }
+    }
+}
+
+impl From<NotificationHandover> for Notification {
+    fn from(n: NotificationHandover) -> Self {
+        let datetime = DateTime::parse_from_rfc3339(&n.received)
+            .map(|d| d.with_timezone(&Local))
+            .unwrap_or_else(|_| Local::now());
+        let age = (Local::now() - datetime).to_std().unwrap_or(Duration::ZERO);
+
+        Notification {
+            app_name: n.app_name,
+            summary: n.summary,
+            body: n.body,
+            urgency: n.urgency,
+            received_at: instant_ago(age),
+            expired_at: n.expires_in_ms.map(|ms| Instant::now() + Duration::from_millis(ms)),
+            app_icon: n.app_icon,
+            id: n.id,
+            unmounting: n.unmounting,
+            reboot: n.reboot,
+            replaces_id: n.replaces_id,
+            unmounted: n.unmounted,
+            datetime,
+            actions: n.actions,
+            color: n.color,
+            image_path: n.image_path,
+            // Loaded again from image_path or app_icon, image-data is lost
+            icon: None,
+            progress: n.progress,
+            count: n.count.max(1),
+        }
+    }
+}
@@ -1,10 +1,10 @@
 use smithay_client_toolkit::{
-    compositor::CompositorHandler, delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm, output::{OutputHandler, OutputState}, registry::{ProvidesRegistryState, RegistryState}, registry_handlers, shell::wlr_layer::{LayerShellHandler, LayerSurface, LayerSurfaceConfigure}, shm::{Shm, ShmHandler, slot::{Buffer, SlotPool}}
+    activation::{ActivationHandler, ActivationState, RequestDataExt}, compositor::CompositorHandler, delegate_activation, delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm, output::{OutputHandler, OutputState}, registry::{ProvidesRegistryState, RegistryState}, registry_handlers, shell::wlr_layer::{LayerShellHandler, LayerSurface, LayerSurfaceConfigure}, shm::{Shm, ShmHandler, slot::{Buffer, SlotPool}}
 };
-use wayland_client::{Connection, QueueHandle, protocol::{wl_compositor, wl_region, wl_shm}};
+use wayland_client::{Connection, QueueHandle, protocol::{wl_compositor, wl_region, wl_seat, wl_shm, wl_surface}};
 use cairo::{Context, Format, ImageSurface};
 
-use std::{num::NonZeroU32, time::{Duration, Instant}};
+use std::{num::NonZeroU32, sync::mpsc::Sender, time::{Duration, Instant}};
 
 use smithay_client_toolkit::shell::WaylandSurface;
 
@@ -12,8 +12,12 @@ use std::collections::HashMap;
 
 use wayland_client::Dispatch;
 use colored::Colorize;
+use chrono::Local;
 
-use crate::{config::{Config, FrameColor}, data::{AlarmIcon, BatteryDevice, IconChange}, dbg_println, notifications::Notification, pills::{Pill, PillModuleTrait}, security::MicCameraStatus, utils::{TweenState, draw_smart_border, log_to_file, mix_color, rounded_rect_gradient}};
+use crate::{config::{Config, FrameColor}, countdown::CountdownDirection, data::{AlarmIcon, BatteryDevice, IconChange}, dbg_println, dnd::DndState, events::{Event, EventBus}, handover::{Handover, IconHandover, NotificationHandover}, history::History, icons::IconCache, notifications::{CloseReason, Notification, NotificationSignal, FloodGuard, RecentNotifications, RuleVerdict, apply_rules, collapse_into_group, enforce_queue_cap, generate_id, insert_notification, reserve_ids}, pills::{PILL_FONT_SIZE, Pill, PillModuleTrait}, security::MicCameraStatus, utils::{TweenState, draw_smart_border, get_color_gradient, log_to_file, mix_color, rounded_rect_gradient, cr_text_layout, select_icon}};
+
+/// Icons Heimdallr manages itself: they survive a Ratatoskr disconnection and the `icon` commands can't touch them
+pub const INTERNAL_ICONS: &[&str] = &["dnd", "ratatoskr", "reboot", "timer"];
 
 static mut AVG_DUR: u128 = 0;
 static mut AVG_CNT: i64 = -5;
@@ -29,7 +33,9 @@ pub struct HeimdallrLayer {
     pub(crate) first_configure: bool,
     // pub(crate) input_region: Option<wl_region::WlRegion>,
     pub(crate) icons: HashMap<String, AlarmIcon>,
-    // pub(crate) battery_integrated: Option<crate::battery::BatteryStats>,
+    /// Icons set through commands, with their optional expiration; Ratatoskr doesn't own them
+    pub(crate) external_icons: HashMap<String, Option<Instant>>,
+    pub(crate) battery_integrated: Option<crate::battery::BatteryStats>,
     pub(crate) needs_redraw: bool,
     pub(crate) last_redraw: Instant,
     pub(crate) redraw_interval: [Duration; 2],
@@ -37,18 +43,47 @@ pub struct HeimdallrLayer {
     pub(crate) current_buffer_idx: usize,
     pub(crate) config: crate::config::Config,
     pub(crate) notifications: Vec<crate::notifications::Notification>,
+    /// Notification picked with `notification next/prev/show`, None shows the first of the queue
+    pub(crate) notification_cursor: Option<u32>,
+    /// Set by `notification hide`: the queue is kept, the pill is back to normal until something new arrives
+    pub(crate) notifications_hidden: bool,
+    /// In memory only until main loads the file from the state dir
+    pub(crate) history: History,
+    pub(crate) dnd: DndState,
+    /// For grouping.dedupe_window
+    recent_notifications: RecentNotifications,
+    /// Rate limits of flood, by app
+    flood_guard: FloodGuard,
+    /// Theme lookups of notification icons, cleared on reload
+    pub(crate) icon_cache: IconCache,
     pub(crate) wob_value: TweenState,
     pub(crate) wob_expiration: Option<Instant>,
+    /// Fades the kind icon, independently from the value: a muted 0% is still shown
+    pub(crate) wob_visibility: TweenState,
+    pub(crate) wob_kind: Option<String>,
+    pub(crate) wob_muted: bool,
+    /// Replaces the color of the kind until the next value
+    pub(crate) wob_color: Option<(f64, f64, f64, f64)>,
+    /// OSD notifications shown in the wob, signaled as expired when it hides
+    pub(crate) wob_osd_ids: Vec<u32>,
     pub(crate) ratatoskr_connected: bool,
     // pub(crate) animator: Animator,
     // pub(crate) frame_model: FrameModel,
     pub(crate) is_waiting_for_frame: bool,
-    // pub(crate) security: crate::security::MicCameraStatus,
+    pub(crate) security: crate::security::MicCameraStatus,
     pub(crate) batteries: Vec<BatteryDevice>,
     pub(crate) batteries_pristine: bool,
     // pub(crate) timer: Countdown,
     pub pill_container: Pill,
-    pub(crate) pills_are_animating: bool
+    pub(crate) pills_are_animating: bool,
+    pub(crate) events: EventBus,
+    /// Signals for the apps that sent the notifications, None until the D-Bus server runs
+    pub(crate) notification_signals: Option<Sender<NotificationSignal>>,
+    /// xdg-activation, to hand a token to apps whose action was invoked; None if the compositor lacks it
+    pub(crate) activation: Option<ActivationState>,
+    pub(crate) timer_finished_sent: bool,
+    /// Set by the `shutdown` command, the main loop exits once the reply is out
+    pub(crate) shutdown_requested: bool
 
 }
 
@@ -71,8 +106,9 @@ impl HeimdallrLayer {
             first_configure: true,
             // input_region: Some(empty_region),
             icons: HashMap::new(),
+            external_icons: HashMap::new(),
             ratatoskr_connected: false,
-            // battery_integrated: None,
+            battery_integrated: None,
             needs_redraw: true,
             last_redraw: Instant::now(),
             redraw_interval: [Duration::from_millis(500), Duration::from_millis(60_000)],
@@ -80,28 +116,52 @@ impl HeimdallrLayer {
             current_buffer_idx: 0,
             config,
             notifications: vec![],
+            notification_cursor: None,
+            notifications_hidden: false,
+            history: History::default(),
+            dnd: DndState::default(),
+            recent_notifications: RecentNotifications::default(),
+            flood_guard: FloodGuard::default(),
+            icon_cache: IconCache::default(),
             // notification_idx: 0,
             wob_expiration: None,
             wob_value: TweenState::new(0.0),
+            wob_visibility: TweenState::new(0.0),
+            wob_kind: None,
+            wob_muted: false,
+            wob_color: None,
+            wob_osd_ids: Vec::new(),
             // animator: Animator::new(),
             // frame_model: FrameModel::new(),
             is_waiting_for_frame: false,
-            // security: MicCameraStatus { mic_active: vec!(), camera_active: vec!(), pristine: false },
+            security: MicCameraStatus { mic_active: vec!(), camera_active: vec!(), pristine: false },
             batteries: vec![],
             batteries_pristine: false,
             // timer: Countdown::new(),
             pill_container: Pill::new(),
-            pills_are_animating: false
+            pills_are_animating: false,
+            events: EventBus::new(),
+            notification_signals: None,
+            activation: None,
+            timer_finished_sent: false,
+            shutdown_requested: false
         }
     }
 
     pub fn update_security_data (&mut self, data: MicCameraStatus) {
         self.pill_container.update_data_security(&data);
-        // self.security = data; // TODO: Deprecated? Remove it?
+        if self.security.mic_active != data.mic_active || self.security.camera_active != data.camera_active {
+            self.events.emit(Event::SecurityChanged { mic_active: data.mic_active.clone(), camera_active: data.camera_active.clone() });
+        }
+        self.security = data;
     }
 
     pub fn update_battery_data (&mut self, data: Option<crate::battery::BatteryStats>) {
-        // self.battery_integrated = data;
+        let old_state = self.battery_integrated.as_ref().map(|b| b.state.clone());
+        if old_state != data.as_ref().map(|b| b.state.clone()) {
+            self.events.emit(Event::BatteryChanged { battery: data.clone() });
+        }
+        self.battery_integrated = data.clone();
         if self.pill_container.update_data_battery(data, self.config.show_watts) {
             self.pill_container.recalculate_normal_target();
             self.request_redraw("pill_container animation");
@@ -114,12 +174,139 @@ impl HeimdallrLayer {
         let _ = self.pill_container.update_data_devices(self.batteries.clone());
     }
 
+    /// JSON snapshot of what the HUD knows and shows, returned by the `query` command
+    pub fn snapshot (&self) -> serde_json::Value {
+        serde_json::json!({
+            "icons": self.icons,
+            "notifications": self.notifications.iter().map(|n| n.to_json()).collect::<Vec<_>>(),
+            // Index in "notifications" of the one in the pill, null in normal mode
+            "notification_shown": self.shown_index(),
+            "countdown": self.pill_container.countdown().snapshot(),
+            "battery": self.battery_integrated,
+            "devices": self.batteries,
+            "security": self.security,
+            "ratatoskr_connected": self.ratatoskr_connected,
+            "dnd": self.dnd.to_json(),
+            "wob": {
+                "visible": self.wob_expiration.is_some(),
+                "value": self.wob_value.target(),
+                "kind": self.wob_kind,
+                "muted": self.wob_muted
+            }
+        })
+    }
+
+    /// What the instance started with --replace needs to continue where we are
+    pub fn handover (&self) -> Handover {
+        Handover {
+            timer: self.pill_container.countdown().handover(),
+            notifications: self.notifications.iter().map(NotificationHandover::from).collect(),
+            icons: self.external_icons.iter().filter_map(|(id, expiration)| {
+                let icon = self.icons.get(id)?;
+                Some(IconHandover {
+                    id: id.clone(),
+                    symbol: icon.symbol.clone(),
+                    warn: icon.warn,
+                    info: icon.info.clone(),
+                    ttl_ms: expiration.map(|exp| exp.saturating_duration_since(Instant::now()).as_millis() as u64)
+                })
+            }).collect(),
+            dnd: self.dnd.manual
+        }
+    }
+
+    pub fn restore (&mut self, handover: Handover) {
+        if let Some(timer) = &handover.timer {
+            self.pill_container.restore_countdown(timer);
+            // A timer that already rang must not ring again
+            self.timer_finished_sent = self.pill_container.countdown().time_remaining().0;
+        }
+
+        for icon in handover.icons {
+            self.set_external_icon(&icon.id, &icon.symbol, icon.warn, icon.ttl_ms.map(Duration::from_millis), icon.info);
+        }
+
+        // The schedule is checked first, or it would reset the manual choice right away
+        self.check_dnd_schedule();
+        let was_active = self.dnd.is_active();
+        self.dnd.manual = handover.dnd;
+        if was_active != self.dnd.is_active() {
+            self.dnd_changed();
+        }
+
+        if let Some(max_id) = handover.notifications.iter().map(|n| n.id).max() {
+            reserve_ids(max_id);
+        }
+        self.notifications = handover.notifications.into_iter().map(Notification::from).collect();
+        for notif in &mut self.notifications {
+            notif.load_icon(&mut self.icon_cache, self.config.icon_theme.as_deref());
+        }
+        self.notifications.sort_by_key(|item| (item.expired_at.is_none(), item.expired_at));
+        self.sync_notifications();
+        self.request_redraw("state restored");
+    }
+
     pub fn set_countdown (&mut self, input: &str) -> Result<u64, &'static str> {
-        self.pill_container.set_countdown(input)
+        let was_active = self.pill_container.countdown().is_active();
+        let secs = self.pill_container.set_countdown(input)?;
+
+        let countdown = self.pill_container.countdown();
+        match input.trim() {
+            "p" | "r" => {}, // pause and resume aren't worth an event
+            _ if !countdown.is_active() => {
+                if was_active { self.events.emit(Event::TimerStopped); }
+            },
+            _ => {
+                self.timer_finished_sent = false;
+                self.events.emit(Event::TimerStarted { direction: countdown.direction.clone(), seconds: secs });
+            }
+        }
+        Ok(secs)
+    }
+
+    /// "on", "off", "toggle" or "auto", see DndState::set
+    pub fn set_dnd(&mut self, mode: &str) -> Result<bool, String> {
+        if self.dnd.set(mode)? {
+            self.dnd_changed();
+        }
+        Ok(self.dnd.is_active())
+    }
+
+    fn check_dnd_schedule(&mut self) {
+        if self.dnd.update_schedule(&self.config.dnd, Local::now().naive_local()) {
+            self.dnd_changed();
+        }
+    }
+
+    /// Shows or hides the DND glyph among the warnings
+    fn dnd_changed(&mut self) {
+        let active = self.dnd.is_active();
+        if active {
+            self.add_icon("dnd", "󰂛", get_color_gradient(0.0), 0.0, Some("Do not disturb".to_string()));
+        } else {
+            self.remove_icon("dnd");
+        }
+        self.events.emit(Event::DndChanged { active });
+        self.request_redraw("dnd changed");
+    }
+
+    fn check_timer_finished(&mut self) {
+        let countdown = self.pill_container.countdown();
+        if !self.timer_finished_sent && countdown.is_active() && countdown.direction == CountdownDirection::Down && countdown.time_remaining().0 {
+            self.timer_finished_sent = true;
+            self.events.emit(Event::TimerFinished);
+        }
     }
 
     pub fn check_redraw_timeout(&mut self) {
 
+        if self.expire_external_icons() {
+            self.request_redraw("external icon expired");
+        }
+        self.check_timer_finished();
+        self.check_dnd_schedule();
+        self.history.compact_if_due();
+
         // if self.pill_container.is_countdown_active() && self.last_redraw.elapsed() > Duration::from_secs(1) {
         if self.pill_container.update_data_countdown() {
             self.request_redraw("timer tick");
@@ -127,6 +314,11 @@ impl HeimdallrLayer {
         } else if self.pill_container.update_data_clock() {
             self.request_redraw("time");
         }
+
+        // Keeps "5 min ago" current, and applies a reloaded layout
+        if self.sync_notifications() {
+            self.request_redraw("notification layout");
+        }
     }
     
     pub fn request_redraw(&mut self, _reason: &str) {
@@ -149,12 +341,17 @@ impl HeimdallrLayer {
         // Check if wob-like must be closed
         if let Some(exp) = self.wob_expiration {
             if Instant::now() > exp {
-                self.wob_value.set_target(0.0);
+                // The value is kept, so the icon doesn't change level while fading out
+                self.wob_visibility.set_target(0.0);
                 self.wob_expiration = None;
+                for id in std::mem::take(&mut self.wob_osd_ids) {
+                    self.notification_closed(id, CloseReason::Expired);
+                }
             }
         }
 
-        let animating = self.wob_value.step() || self.pills_are_animating;
+        let wob_animating = self.wob_value.step() | self.wob_visibility.step();
+        let animating = wob_animating || self.pills_are_animating;
         if !animating { // Now, we skip calling draw only if we are not animating something
 
             if !self.needs_redraw {
@@ -314,18 +511,67 @@ impl HeimdallrLayer {
         let mut pill_bg_steps = vec![(0.0, pill_bg_color)];
 
         // wob-like
-        let wob_ratio = self.wob_value.value();
+        let wob_visibility = self.wob_visibility.value();
+        let wob_ratio = self.wob_value.value() * wob_visibility;
+        let wob_kind = self.config.wob.kind(self.wob_kind.as_deref());
+        // The whole pill width is the kind's max, so 100% is not at the end for boosted values
+        let wob_max = wob_kind.max;
+        let wob_kind_color = self.wob_color.unwrap_or(wob_kind.color);
+        let wob_color_base = if self.wob_muted {
+            // Muted values keep their level but lose most of their color
+            mix_color(pill_bg_color, (0.5, 0.5, 0.5, pill_bg_color.3), 0.5)
+        } else {
+            (wob_kind_color.0, wob_kind_color.1, wob_kind_color.2, pill_bg_color.3 * wob_kind_color.3)
+        };
+        let wob_over_color = if self.wob_muted {
+            wob_color_base
+        } else {
+            (wob_kind.over_color.0, wob_kind.over_color.1, wob_kind.over_color.2, pill_bg_color.3)
+        };
         if wob_ratio > 0.0 {
-            let wob_color_base = (0.6, 0.6, 0.7, pill_bg_color.3);
-            let wob_color = mix_color(pill_bg_color, wob_color_base, wob_ratio.max(0.5));
-            pill_border_color = Some(mix_color(pill_border_color.unwrap_or((0.0, 0.0, 0.0, 0.0)), wob_color_base, wob_ratio));
+            let intensity = wob_ratio.clamp(0.5, 1.0);
+            let wob_color = mix_color(pill_bg_color, wob_color_base, intensity);
+            let border_base = if wob_ratio > 1.0 { wob_over_color } else { wob_color_base };
+            pill_border_color = Some(mix_color(pill_border_color.unwrap_or((0.0, 0.0, 0.0, 0.0)), border_base, wob_ratio.min(1.0)));
             let mut steps = vec![(0.0, wob_color)];
-            steps.push((wob_ratio, pill_bg_color));
+            if wob_ratio > 1.0 {
+                steps.push((1.0 / wob_max, mix_color(pill_bg_color, wob_over_color, intensity)));
+            }
+            steps.push((wob_ratio / wob_max, pill_bg_color));
             pill_bg_steps = steps;
         }
 
         rounded_rect_gradient(&cr, rect_left, rect_top, rect_width, rect_height, r, pill_bg_steps, crate::utils::GradientDirection::Horizontal, false, pill_border_color);
 
+        if wob_max > 1.0 && wob_visibility > 0.0 {
+            // Marker at 100%
+            let marker_x = (rect_left + rect_width / wob_max).round() + 0.5;
+            cr.set_source_rgba(wob_over_color.0, wob_over_color.1, wob_over_color.2, wob_visibility);
+            cr.set_line_width(1.0);
+            cr.move_to(marker_x, rect_top + 3.0);
+            cr.line_to(marker_x, rect_top + rect_height - 3.0);
+            let _ = cr.stroke();
+        }
+
+        let wob_icon = if self.wob_muted && wob_kind.muted_icon.is_some() {
+            wob_kind.muted_icon.clone()
+        } else {
+            // Boosted values keep the highest icon
+            select_icon(0.0, 1.0, self.wob_value.target(), &wob_kind.icons)
+        };
+        if let (true, Some(icon)) = (wob_visibility > 0.0, wob_icon) {
+            // Square badge on the left of the pill, fading with the indicator
+            let badge_left = rect_left - rect_height - 4.0;
+            let fade = |c: (f64, f64, f64, f64)| (c.0, c.1, c.2, c.3 * wob_visibility);
+            rounded_rect_gradient(cr, badge_left, rect_top, rect_height, rect_height, r, vec![(0.0, fade(pill_bg_color))], crate::utils::GradientDirection::Horizontal, false, Some(fade(wob_color_base)));
+            if let Ok((layout, sizes)) = cr_text_layout(cr, &icon, PILL_FONT_SIZE, None) {
+                let (red, green, blue, alpha) = fade(if self.wob_muted { (0.6, 0.6, 0.6, 1.0) } else { wob_kind_color });
+                cr.set_source_rgba(red, green, blue, alpha);
+                cr.move_to(badge_left + (rect_height - sizes.0) / 2.0, rect_top + (rect_height - sizes.1) / 2.0);
+                pangocairo::functions::show_layout(cr, &layout);
+            }
+        }
+
         self.pill_container.draw(&cr, rect_width_end, rect_height, rect_left_end, rect_top);
     }
 
@@ -392,43 +638,95 @@ impl HeimdallrLayer {
     pub fn update_notification_list (&mut self, new_notif_opt: Option<Notification>) -> bool {
 
         let mut changed: bool = false;
-        if let Some(new_notif) = new_notif_opt {
-            let mut custom_replace = None;
-            if new_notif.unmounted {
-                let to_be_replaced = self.notifications.iter().find(|x| x.unmounting);
-                if let Some(notif) = to_be_replaced {
-                    custom_replace = Some(notif.id);
+        if let Some(mut new_notif) = new_notif_opt {
+            let id = new_notif.id;
+            let verdict = apply_rules(&self.config.rules, &mut new_notif);
+            let duplicate = new_notif.replaces_id == 0
+                && self.config.grouping.dedupe_window.is_some_and(|window| self.recent_notifications.is_duplicate(&new_notif, window));
+            let updates_shown = new_notif.replaces_id > 0 && self.notifications.iter().any(|n| n.id == new_notif.replaces_id);
+            // With DND, non-critical notifications stay in the history, unless they update one already shown
+            let dnd_hidden = self.dnd.is_active() && new_notif.urgency < 2 && !updates_shown;
+            // Only what would reach the queue counts against the rate limit; critical ones and updates never do
+            let flooding = verdict == RuleVerdict::Show && !dnd_hidden && !duplicate && new_notif.urgency < 2 && !updates_shown
+                && !self.flood_guard.allow(&new_notif.app_name, &self.config.flood, Instant::now());
+            if verdict == RuleVerdict::Drop {
+                log_to_file(format!("Notification {} from {} dropped by a rule", new_notif.id, new_notif.app_name));
+            } else if duplicate {
+                log_to_file(format!("Notification {} from {} dropped as a duplicate", new_notif.id, new_notif.app_name));
+            } else if flooding {
+                log_to_file(format!("Notification {} from {} over the rate limit, merged", new_notif.id, new_notif.app_name));
+                self.flood_guard.merge(&mut self.notifications, &new_notif, &mut self.icon_cache, self.config.icon_theme.as_deref());
+                changed = true;
+            } else {
+                new_notif.load_icon(&mut self.icon_cache, self.config.icon_theme.as_deref());
+                self.events.emit(Event::NotificationReceived {
+                    id: new_notif.id,
+                    app_name: new_notif.app_name.clone(),
+                    summary: new_notif.summary.clone(),
+                    body: new_notif.body.clone(),
+                    urgency: new_notif.urgency
+                });
+
+                self.history.record(&new_notif);
+
+                // let id = list.iter().map(|x| x.id).max().unwrap_or();
+
+                if verdict == RuleVerdict::HistoryOnly {
+                    log_to_file(format!("Notification {} from {} sent to the history by a rule", new_notif.id, new_notif.app_name));
+                } else if dnd_hidden {
+                    log_to_file(format!("Do not disturb: notification {} from {} not shown", new_notif.id, new_notif.app_name));
+                } else {
+                    // "Slack ×7": the newest notification in the queue makes way for this one if they come from the same app
+                    let grouped = if self.config.grouping.enabled {
+                        collapse_into_group(&mut self.notifications, &mut new_notif, self.config.grouping.same_summary)
+                    } else {
+                        None
+                    };
+                    if let Some(id) = grouped {
+                        self.notification_closed(id, CloseReason::Replaced);
+                    }
+
+                    // An in-place update keeps its id, so the sender must not be told it was closed
+                    let outcome = insert_notification(&mut self.notifications, new_notif);
+                    // Something new goes back to the head of the queue, an update doesn't move the user away
+                    if !outcome.updated_in_place {
+                        self.notification_cursor = None;
+                        self.notifications_hidden = false;
+                    }
+                    for id in outcome.replaced {
+                        self.notification_closed(id, CloseReason::Replaced);
+                    }
+
+                    changed = true;
                 }
             }
-            if let Some(rep) = custom_replace {
-                self.notifications.retain(|n| n.id != rep);
-            }
 
-            if new_notif.replaces_id > 0 {
-                self.notifications.retain(|n| n.id != new_notif.replaces_id);
+            // The sender got an id back from Notify and waits for it to be closed, or it leaks the notification.
+            // An update that didn't make it leaves the one it updates alone.
+            if !self.notifications.iter().any(|n| n.id == id) {
+                self.notification_closed(id, CloseReason::NotShown);
             }
+        }
 
-            // let id = list.iter().map(|x| x.id).max().unwrap_or();
-            
-            self.notifications.insert(0, new_notif);
-
-            self.notifications.sort_by_key(|item| {
-                // false comes before true, so Some items (notifications with expiration) go to the front, None (notif. without expiration) to the back.
-                // Within Some, notifications are sorted chronologically (oldest notification first).
-                (item.expired_at.is_none(), item.expired_at)
-            });
-
+        for id in enforce_queue_cap(&mut self.notifications, self.config.flood.max_queue) {
+            log_to_file(format!("Notification {id} dropped, the queue is full"));
+            self.notification_closed(id, CloseReason::Expired);
             changed = true;
         }
 
-        let a = self.notifications.len();
-        self.notifications.retain(|n| n.expired_at.is_none() || (n.expired_at.unwrap() > Instant::now()));
-        let b = self.notifications.len();
+        let now = Instant::now();
+        let (expired, alive): (Vec<Notification>, Vec<Notification>) = std::mem::take(&mut self.notifications)
+            .into_iter()
+            .partition(|n| n.expired_at.is_some_and(|exp| exp <= now));
+        self.notifications = alive;
+        for notif in &expired {
+            self.notification_closed(notif.id, CloseReason::Expired);
+        }
 
-        changed = changed || (a != b);
+        changed = changed || !expired.is_empty();
 
         if changed {
-            self.pill_container.update_data_notifications(&self.notifications);
+            self.sync_notifications();
             self.request_redraw("notifications updated");
         }
         changed
@@ -436,17 +734,38 @@ impl HeimdallrLayer {
     }
 }
 
+impl HeimdallrLayer {
+    /// Shows a history entry again, with a new id so that it can't be mistaken for the original. Not recorded again.
+    pub fn replay_history(&mut self, seq: u64) -> Result<u32, String> {
+        let entry = self.history.get(seq).ok_or_else(|| format!("No history entry {seq}"))?;
+        let mut notif = entry.to_notification(generate_id());
+        let id = notif.id;
+        notif.load_icon(&mut self.icon_cache, self.config.icon_theme.as_deref());
+
+        insert_notification(&mut self.notifications, notif);
+        self.notification_cursor = Some(id);
+        self.notifications_hidden = false;
+        self.sync_notifications();
+        self.request_redraw("history replay");
+        Ok(id)
+    }
+}
+
 impl HeimdallrLayer { // This is for icon/notifications/stuff management, I like to keep it separated
     pub fn add_icon(&mut self, id: &str, symbol: &str, color: (f64, f64, f64, f64), warn: f64, info: Option<String>) -> IconChange {
 
         let mut already_present = false;
         if let Some(found) = self.icons.get(id) {
             already_present = true;
-            if f64::abs(found.warn - warn) < 0.05 && found.info == info {
+            if found.symbol == symbol && f64::abs(found.warn - warn) < 0.05 && found.info == info {
                 return IconChange::None;
             }
         }
 
+        if !already_present {
+            self.events.emit(Event::IconAdded { id: id.to_string(), symbol: symbol.to_string(), warn });
+        }
+
         self.icons.insert(
             id.to_string(),
             AlarmIcon {
@@ -471,8 +790,44 @@ impl HeimdallrLayer { // This is for icon/notifications/stuff management, I like
         }
     }
 
+    pub fn set_external_icon(&mut self, id: &str, symbol: &str, warn: f64, ttl: Option<Duration>, info: Option<String>) -> IconChange {
+        self.external_icons.insert(id.to_string(), ttl.map(|t| Instant::now() + t));
+        self.add_icon(id, symbol, get_color_gradient(warn), warn, info)
+    }
+
+    fn expire_external_icons(&mut self) -> bool {
+        let now = Instant::now();
+        let expired: Vec<String> = self.external_icons
+            .iter()
+            .filter(|(_, exp)| exp.is_some_and(|e| e <= now))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let mut removed = false;
+        for id in expired {
+            removed |= self.remove_icon(&id);
+        }
+        removed
+    }
+
+    /// Removes the icons fed by Ratatoskr, keeping the ones set through commands
+    pub fn clear_ratatoskr_icons(&mut self) {
+        let external = &self.external_icons;
+        let events = &self.events;
+        self.icons.retain(|id, _| {
+            let keep = external.contains_key(id) || INTERNAL_ICONS.contains(&id.as_str());
+            if !keep { events.emit(Event::IconRemoved { id: id.clone() }); }
+            keep
+        });
+        self.pill_container.update_data_warnings(&self.icons);
+    }
+
     pub fn remove_icon(&mut self, id: &str) -> bool {
+        self.external_icons.remove(id);
         let removed = self.icons.remove(id).is_some();
+        if removed {
+            self.events.emit(Event::IconRemoved { id: id.to_string() });
+        }
         if removed {
             if self.pill_container.update_data_warnings(&self.icons) {
                 // self.pill_container.recalculate_normal_target();
@@ -482,27 +837,163 @@ impl HeimdallrLayer { // This is for icon/notifications/stuff management, I like
         removed
     }
 
+    fn notification_closed(&mut self, id: u32, reason: CloseReason) {
+        self.events.emit(Event::NotificationClosed { id, reason });
+        if let Some(tx) = &self.notification_signals {
+            let _ = tx.send(NotificationSignal::Closed { id, reason });
+        }
+    }
+
+    /// Invokes an action of the notification currently shown, which is then dismissed
+    pub fn invoke_notification_action(&mut self, qh: &QueueHandle<Self>, which: &str) -> Result<String, String> {
+        let notif = self.shown_notification().ok_or("No notification shown")?;
+        let key = notif.find_action(which)
+            .ok_or_else(|| format!("Notification {} has no action {}", notif.id, which))?
+            .to_string();
+        let id = notif.id;
+
+        self.events.emit(Event::NotificationAction { id, key: key.clone() });
+        match &self.activation {
+            // The app gets its signals when the token arrives, see new_token
+            Some(activation) => activation.request_token_with_data::<Self, ActionRequest>(qh, ActionRequest { id, key: key.clone() }),
+            None => self.action_invoked(id, key.clone(), None),
+        }
+        Ok(key)
+    }
+
+    fn action_invoked(&mut self, id: u32, key: String, token: Option<String>) {
+        if let Some(tx) = &self.notification_signals {
+            if let Some(token) = token {
+                let _ = tx.send(NotificationSignal::ActivationToken { id, token });
+            }
+            let _ = tx.send(NotificationSignal::ActionInvoked { id, key });
+        }
+        // Closed after ActionInvoked, or the app could forget about the action
+        if let Some(pos) = self.notifications.iter().position(|n| n.id == id) {
+            self.notifications.remove(pos);
+            self.notification_closed(id, CloseReason::Dismissed);
+            self.sync_notifications();
+            self.request_redraw("notification action");
+        }
+    }
+
+    /// CloseNotification from the sender; false if the notification is already gone
+    pub fn close_notification(&mut self, id: u32) -> bool {
+        if let Some(pos) = self.wob_osd_ids.iter().position(|&osd| osd == id) {
+            self.wob_osd_ids.remove(pos);
+            self.notification_closed(id, CloseReason::Closed);
+            return false;
+        }
+        let Some(pos) = self.notifications.iter().position(|n| n.id == id) else { return false; };
+        self.notifications.remove(pos);
+        self.notification_closed(id, CloseReason::Closed);
+        self.sync_notifications();
+        true
+    }
+
+    /// Dismisses the notification shown; the next one in the queue takes its place
     pub fn remove_notification(&mut self) -> bool {
-        if self.notifications.len() > 0 {
-            self.notifications.remove(0);
-            self.pill_container.update_data_notifications(&self.notifications);
+        if let Some(pos) = self.shown_index() {
+            let removed = self.notifications.remove(pos);
+            self.notification_closed(removed.id, CloseReason::Dismissed);
+            self.notification_cursor = self.notifications.get(pos.min(self.notifications.len().saturating_sub(1))).map(|n| n.id);
+            self.sync_notifications();
             true
         } else {
             false
         }
     }
-    
-    /* #[deprecated]
-    pub fn show_notification(&mut self, new_idx: i32) -> bool {
-        eprintln!("{} ({new_idx})", "Removed functionality".yellow());
-        false
-    } */
-
-    pub fn show_value(&mut self, value: f64, _kind: Option<&str>) -> bool {
-        let target = value.clamp(0.0, 1.0);
-        let changed = self.wob_expiration.is_none() || (self.wob_value.target() - target).abs() > f64::EPSILON;
-        self.wob_expiration = Some(Instant::now() + Duration::from_millis(2000));
+
+    /// Index in the queue of the notification in the pill, None in normal mode
+    fn shown_index(&self) -> Option<usize> {
+        if self.notifications_hidden || self.notifications.is_empty() {
+            return None;
+        }
+        Some(self.notification_cursor
+            .and_then(|id| self.notifications.iter().position(|n| n.id == id))
+            .unwrap_or(0))
+    }
+
+    pub fn shown_notification(&self) -> Option<&Notification> {
+        self.shown_index().map(|pos| &self.notifications[pos])
+    }
+
+    fn sync_notifications(&mut self) -> bool {
+        self.pill_container.update_data_notifications(&self.notifications, self.shown_index(), &self.config.notification_layout)
+    }
+
+    /// Shows the notification `offset` places after the current one, wrapping around; returns (position, count), 1-based
+    pub fn browse_notifications(&mut self, offset: isize) -> Result<(usize, usize), String> {
+        let len = self.notifications.len();
+        if len == 0 {
+            return Err("No notification queued".to_string());
+        }
+        // Coming back from normal mode shows the current one again, without moving
+        let current = self.shown_index();
+        let pos = match current {
+            Some(pos) => (pos as isize + offset).rem_euclid(len as isize) as usize,
+            None => self.notification_cursor
+                .and_then(|id| self.notifications.iter().position(|n| n.id == id))
+                .unwrap_or(0),
+        };
+        self.show_notification_at(pos)
+    }
+
+    /// position is 1-based, like the counter in the pill
+    pub fn show_notification(&mut self, position: usize) -> Result<(usize, usize), String> {
+        let len = self.notifications.len();
+        if position == 0 || position > len {
+            return Err(format!("No notification {position} ({len} queued)"));
+        }
+        self.show_notification_at(position - 1)
+    }
+
+    fn show_notification_at(&mut self, pos: usize) -> Result<(usize, usize), String> {
+        // A notification picked by hand must not vanish while it is being read
+        let min_expiration = Instant::now() + Duration::from_secs(3);
+        let notif = &mut self.notifications[pos];
+        notif.expired_at = notif.expired_at.map(|exp| exp.max(min_expiration));
+
+        self.notification_cursor = Some(notif.id);
+        self.notifications_hidden = false;
+        self.sync_notifications();
+        self.request_redraw("notification browsed");
+        Ok((pos + 1, self.notifications.len()))
+    }
+
+    /// Back to normal mode, keeping the queue; false if nothing was shown
+    pub fn hide_notifications(&mut self) -> bool {
+        if self.shown_index().is_none() {
+            return false;
+        }
+        self.notifications_hidden = true;
+        self.sync_notifications();
+        self.request_redraw("notifications hidden");
+        true
+    }
+
+    /// Shows an OSD notification in the wob, its id is closed with it
+    pub fn show_osd(&mut self, id: u32, value: f64, kind: &str) {
+        self.show_value(value, Some(kind), false, None);
+        if !self.wob_osd_ids.contains(&id) {
+            self.wob_osd_ids.push(id);
+        }
+    }
+
+    pub fn show_value(&mut self, value: f64, kind: Option<&str>, muted: bool, color: Option<(f64, f64, f64, f64)>) -> bool {
+        let target = value.clamp(0.0, self.config.wob.kind(kind).max);
+        let kind = kind.map(|k| k.to_string());
+        let changed = self.wob_expiration.is_none()
+            || (self.wob_value.target() - target).abs() > f64::EPSILON
+            || self.wob_kind != kind
+            || self.wob_muted != muted
+            || self.wob_color != color;
+        self.wob_expiration = Some(Instant::now() + self.config.wob.timeout);
         self.wob_value.set_target(target);
+        self.wob_visibility.set_target(1.0);
+        self.wob_kind = kind;
+        self.wob_muted = muted;
+        self.wob_color = color;
         if changed {
             self.request_redraw("wob value changed");
         }
@@ -559,6 +1050,27 @@ delegate_output!(HeimdallrLayer);
 delegate_shm!(HeimdallrLayer);
 delegate_layer!(HeimdallrLayer);
 delegate_registry!(HeimdallrLayer);
+delegate_activation!(HeimdallrLayer, ActionRequest);
+
+/// Activation token request made for a notification action
+pub struct ActionRequest {
+    id: u32,
+    key: String,
+}
+
+impl RequestDataExt for ActionRequest {
+    fn app_id(&self) -> Option<&str> { None }
+    fn seat_and_serial(&self) -> Option<(&wl_seat::WlSeat, u32)> { None }
+    fn surface(&self) -> Option<&wl_surface::WlSurface> { None }
+}
+
+impl ActivationHandler for HeimdallrLayer {
+    type RequestData = ActionRequest;
+
+    fn new_token(&mut self, token: String, data: &ActionRequest) {
+        self.action_invoked(data.id, data.key.clone(), Some(token));
+    }
+}
 
 impl ProvidesRegistryState for HeimdallrLayer {
     fn registry(&mut self) -> &mut RegistryState { &mut self.registry_state }
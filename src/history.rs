@@ -0,0 +1,283 @@
+// Notifications received, kept after they leave the pill.
+// Stored as JSON lines in $XDG_STATE_HOME/heimdallr/history-<display>.jsonl and trimmed by HistoryConfig.
+// Every record is appended, updates included (the last line of a seq wins); the file is compacted now and then.
+// Files are written by a thread of their own, the main loop never waits for the disk.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::config::HistoryConfig;
+use crate::markup;
+use crate::notifications::Notification;
+use crate::utils::log_to_file;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Used by `history show`, never reused
+    pub seq: u64,
+    /// Id the sender knows, updates in place share it
+    pub notification_id: u32,
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    /// As received, markup included
+    pub body: String,
+    pub urgency: u8,
+    /// RFC 3339
+    pub datetime: String,
+}
+
+impl HistoryEntry {
+    fn received(&self) -> Option<DateTime<Local>> {
+        DateTime::parse_from_rfc3339(&self.datetime).ok().map(|d| d.with_timezone(&Local))
+    }
+
+    /// Representation used by `history list` and `history search`: body is plain text, body_markup as received
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "seq": self.seq,
+            "notification_id": self.notification_id,
+            "app_name": self.app_name,
+            "app_icon": self.app_icon,
+            "summary": self.summary,
+            "body": markup::strip(&self.body),
+            "body_markup": self.body,
+            "urgency": self.urgency,
+            "datetime": self.datetime,
+        })
+    }
+
+    /// A notification showing this entry again, with a fresh id and the usual 3 seconds for non-critical ones
+    pub fn to_notification(&self, id: u32) -> Notification {
+        Notification {
+            app_name: self.app_name.clone(),
+            summary: self.summary.clone(),
+            body: self.body.clone(),
+            urgency: self.urgency,
+            received_at: Instant::now(),
+            expired_at: if self.urgency < 2 { Some(Instant::now() + Duration::from_secs(3)) } else { None },
+            app_icon: self.app_icon.clone(),
+            id,
+            unmounting: false,
+            reboot: false,
+            replaces_id: 0,
+            unmounted: false,
+            datetime: self.received().unwrap_or_else(Local::now),
+            actions: vec![],
+            color: None,
+            image_path: String::new(),
+            icon: None,
+            progress: None,
+            count: 1,
+        }
+    }
+}
+
+/// Rewrites of the file wait this long, so that a burst of updates or trims costs one
+const COMPACT_DELAY: Duration = Duration::from_secs(2);
+
+enum HistoryWrite {
+    Append(String),
+    /// The whole file
+    Rewrite(String),
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    /// None keeps the history in memory only
+    writer: Option<Sender<HistoryWrite>>,
+    config: HistoryConfig,
+    /// Oldest first
+    entries: VecDeque<HistoryEntry>,
+    next_seq: u64,
+    /// Since when the file holds lines that compacting would drop
+    compact_pending: Option<Instant>,
+}
+
+impl History {
+    pub fn load(path: PathBuf, config: HistoryConfig) -> Self {
+        let lines = fs::read_to_string(&path).unwrap_or_default();
+        // Bodies can be private, a history written by an older version may still be readable by others
+        if path.exists() {
+            let _ = fs::set_permissions(&path, fs::Permissions::from_mode(0o600));
+        }
+        let mut entries: VecDeque<HistoryEntry> = VecDeque::new();
+        for entry in lines.lines().filter_map(|line| serde_json::from_str::<HistoryEntry>(line).ok()) {
+            // An update appended later replaces its entry
+            match entries.iter_mut().rev().find(|e| e.seq == entry.seq) {
+                Some(old) => *old = entry,
+                None => entries.push_back(entry),
+            }
+        }
+        let next_seq = entries.iter().map(|e| e.seq + 1).max().unwrap_or(1);
+        let compact = lines.lines().count() != entries.len();
+
+        let mut history = History { writer: Some(spawn_writer(path)), config, entries, next_seq, compact_pending: None };
+        if history.trim() || compact {
+            history.compact();
+        }
+        history
+    }
+
+    pub fn set_config(&mut self, config: HistoryConfig) {
+        self.config = config;
+        if self.trim() {
+            self.compact();
+        }
+    }
+
+    /// Records a notification; one with replaces_id takes the place of the entry it updates
+    pub fn record(&mut self, notif: &Notification) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let existing = (notif.replaces_id > 0)
+            .then(|| self.entries.iter().rev().find(|e| e.notification_id == notif.id))
+            .flatten();
+        let seq = match existing {
+            Some(entry) => entry.seq,
+            None => {
+                self.next_seq += 1;
+                self.next_seq - 1
+            }
+        };
+        let entry = HistoryEntry {
+            seq,
+            notification_id: notif.id,
+            app_name: notif.app_name.clone(),
+            app_icon: notif.app_icon.clone(),
+            summary: notif.summary.clone(),
+            body: notif.body.clone(),
+            urgency: notif.urgency,
+            datetime: notif.datetime.to_rfc3339(),
+        };
+
+        if let Ok(line) = serde_json::to_string(&entry) {
+            self.send(HistoryWrite::Append(line));
+        }
+        let replaced = match self.entries.iter_mut().find(|e| e.seq == seq) {
+            Some(old) => {
+                *old = entry;
+                true
+            }
+            None => {
+                self.entries.push_back(entry);
+                false
+            }
+        };
+        if (self.trim() || replaced) && self.compact_pending.is_none() {
+            self.compact_pending = Some(Instant::now());
+        }
+    }
+
+    /// Newest first
+    pub fn list(&self, count: usize) -> Vec<&HistoryEntry> {
+        self.entries.iter().rev().take(count).collect()
+    }
+
+    /// Case insensitive regex on app name, summary and body, newest first
+    pub fn search(&self, pattern: &str) -> Result<Vec<&HistoryEntry>, String> {
+        let re = RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid pattern: {e}"))?;
+        Ok(self.entries
+            .iter()
+            .rev()
+            .filter(|e| re.is_match(&e.app_name) || re.is_match(&e.summary) || re.is_match(&markup::strip(&e.body)))
+            .collect())
+    }
+
+    pub fn get(&self, seq: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|e| e.seq == seq)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.compact();
+    }
+
+    /// Compacts the file if it has been waiting for COMPACT_DELAY, called from the main loop
+    pub fn compact_if_due(&mut self) {
+        if self.compact_pending.is_some_and(|since| since.elapsed() >= COMPACT_DELAY) {
+            self.compact();
+        }
+    }
+
+    /// Compacts the file now if needed, before shutting down
+    pub fn flush(&mut self) {
+        if self.compact_pending.is_some() {
+            self.compact();
+        }
+    }
+
+    /// Applies the retention policy, returns true if something was dropped
+    fn trim(&mut self) -> bool {
+        let before = self.entries.len();
+        if let Some(max_age) = self.config.max_age {
+            let oldest = Local::now() - chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::MAX);
+            self.entries.retain(|e| e.received().is_some_and(|d| d >= oldest));
+        }
+        while self.entries.len() > self.config.max_entries {
+            self.entries.pop_front();
+        }
+        self.entries.len() != before
+    }
+
+    /// Rewrites the file with one line per entry
+    fn compact(&mut self) {
+        self.compact_pending = None;
+        let data: String = self.entries.iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| line + "\n")
+            .collect();
+        self.send(HistoryWrite::Rewrite(data));
+    }
+
+    fn send(&self, write: HistoryWrite) {
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(write);
+        }
+    }
+}
+
+/// Writes go through a channel, so appends and rewrites keep their order
+fn spawn_writer(path: PathBuf) -> Sender<HistoryWrite> {
+    let (tx, rx) = mpsc::channel::<HistoryWrite>();
+    std::thread::spawn(move || {
+        for write in rx {
+            let result = match write {
+                HistoryWrite::Append(line) => fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(0o600)
+                    .open(&path)
+                    .and_then(|mut file| writeln!(file, "{line}")),
+                HistoryWrite::Rewrite(data) => rewrite(&path, &data),
+            };
+            if let Err(e) = result {
+                log_to_file(format!("Cannot save notification history to {}: {e}", path.display()));
+            }
+        }
+    });
+    tx
+}
+
+/// Through a temporary file, so that a crash can't leave half a history behind
+fn rewrite(path: &Path, data: &str) -> std::io::Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut file = fs::OpenOptions::new().create(true).write(true).truncate(true).mode(0o600).open(&tmp)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
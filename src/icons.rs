@@ -0,0 +1,280 @@
+// Notification icons: the image-data hint, an image file, or a name looked up in the freedesktop icon theme.
+// Pixels are kept in cairo's ARGB32 layout, at most ICON_MAX_SIZE per side; the pill scales them to its height.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use cairo::{Format, ImageSurface};
+use image::{RgbaImage, imageops::FilterType};
+use resvg::{tiny_skia, usvg::{self, TreeParsing}};
+use zbus::zvariant::Value;
+
+/// Larger icons are scaled down when loaded, nothing bigger is ever drawn
+pub const ICON_MAX_SIZE: u32 = 64;
+/// Largest side accepted from the image-data hint, any app can claim whatever size it likes
+const IMAGE_DATA_MAX_SIZE: usize = 1024;
+/// Names remembered by IconCache, a flood of made-up names only costs a fresh start
+const ICON_CACHE_MAX_ENTRIES: usize = 256;
+
+#[derive(Clone, PartialEq)]
+pub struct IconImage {
+    pub width: u32,
+    pub height: u32,
+    /// Premultiplied ARGB32 in native endianness, rows of width * 4 bytes
+    data: Vec<u8>,
+}
+
+// The pixels would flood the log, where notifications are printed with {:?}
+impl std::fmt::Debug for IconImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IconImage({}x{})", self.width, self.height)
+    }
+}
+
+impl IconImage {
+    /// From straight (not premultiplied) RGBA
+    fn from_rgba(img: RgbaImage) -> Option<Self> {
+        let (w, h) = img.dimensions();
+        if w == 0 || h == 0 {
+            return None;
+        }
+        let img = if w.max(h) > ICON_MAX_SIZE {
+            let scale = ICON_MAX_SIZE as f64 / w.max(h) as f64;
+            let (nw, nh) = (((w as f64 * scale).round() as u32).max(1), ((h as f64 * scale).round() as u32).max(1));
+            image::imageops::resize(&img, nw, nh, FilterType::Triangle)
+        } else {
+            img
+        };
+
+        let data = img.pixels()
+            .flat_map(|p| {
+                let [r, g, b, a] = p.0;
+                let premultiply = |c: u8| (c as u32 * a as u32 / 255) as u8;
+                argb(premultiply(r), premultiply(g), premultiply(b), a)
+            })
+            .collect();
+        Some(IconImage { width: img.width(), height: img.height(), data })
+    }
+
+    /// tiny-skia pixmaps are already premultiplied, only the byte order changes
+    fn from_pixmap(pixmap: tiny_skia::Pixmap) -> Self {
+        let data = pixmap.data()
+            .chunks_exact(4)
+            .flat_map(|p| argb(p[0], p[1], p[2], p[3]))
+            .collect();
+        IconImage { width: pixmap.width(), height: pixmap.height(), data }
+    }
+
+    pub fn to_surface(&self) -> Option<ImageSurface> {
+        let stride = Format::ARgb32.stride_for_width(self.width).ok()? as usize;
+        let row = self.width as usize * 4;
+        let mut data = vec![0u8; stride * self.height as usize];
+        for (dst, src) in data.chunks_exact_mut(stride).zip(self.data.chunks_exact(row)) {
+            dst[..row].copy_from_slice(src);
+        }
+        ImageSurface::create_for_data(data, Format::ARgb32, self.width as i32, self.height as i32, stride as i32).ok()
+    }
+}
+
+fn argb(r: u8, g: u8, b: u8, a: u8) -> [u8; 4] {
+    (((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | b as u32).to_ne_bytes()
+}
+
+/// Decodes the raw "image-data" hint: (width, height, rowstride, has_alpha, bits_per_sample, channels, data)
+pub fn from_image_data(value: &Value<'_>) -> Option<IconImage> {
+    let Value::Structure(s) = value else { return None; };
+    let int = |v: &Value<'_>| match v { Value::I32(n) => u32::try_from(*n).ok(), _ => None };
+
+    let [width, height, rowstride, has_alpha, bits_per_sample, channels, data] = s.fields() else { return None; };
+    let (width, height, rowstride, channels) = (int(width)?, int(height)?, int(rowstride)? as usize, int(channels)? as usize);
+    let has_alpha = matches!(has_alpha, Value::Bool(true));
+    if int(bits_per_sample)? != 8 || channels != if has_alpha { 4 } else { 3 } {
+        return None;
+    }
+    let Value::Array(data) = data else { return None; };
+    let bytes: Vec<u8> = data.get().iter().filter_map(|v| v.downcast_ref::<u8>().copied()).collect();
+    decode_pixels(width, height, rowstride, has_alpha, &bytes)
+}
+
+/// Builds the image from raw rows, None unless the size fits both IMAGE_DATA_MAX_SIZE and the bytes sent
+fn decode_pixels(width: u32, height: u32, rowstride: usize, has_alpha: bool, bytes: &[u8]) -> Option<IconImage> {
+    let channels = if has_alpha { 4 } else { 3 };
+    let (w, h) = (width as usize, height as usize);
+    let row = w * channels;
+    let valid = (1..=IMAGE_DATA_MAX_SIZE).contains(&w)
+        && (1..=IMAGE_DATA_MAX_SIZE).contains(&h)
+        && rowstride >= row
+        && rowstride.checked_mul(h - 1).and_then(|n| n.checked_add(row)).is_some_and(|needed| needed <= bytes.len());
+    if !valid {
+        return None;
+    }
+
+    let img = RgbaImage::from_fn(width, height, |x, y| {
+        let offset = y as usize * rowstride + x as usize * channels;
+        let px = &bytes[offset..offset + channels];
+        image::Rgba([px[0], px[1], px[2], if has_alpha { px[3] } else { 255 }])
+    });
+    IconImage::from_rgba(img)
+}
+
+/// Icons looked up by name in the theme, missing ones included; files are always read again, apps rewrite them
+#[derive(Debug, Default)]
+pub struct IconCache {
+    /// Theme the names were looked up in
+    theme: Option<String>,
+    by_name: HashMap<String, Option<Arc<IconImage>>>,
+}
+
+impl IconCache {
+    /// `source` is a path, a file:// URI or an icon name; names are looked up in `theme`, then in hicolor
+    pub fn load(&mut self, source: &str, theme: Option<&str>) -> Option<Arc<IconImage>> {
+        if source.is_empty() {
+            return None;
+        }
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        if path.starts_with('/') || path.starts_with('~') {
+            return load_file(Path::new(shellexpand::tilde(path).as_ref())).map(Arc::new);
+        }
+
+        if self.theme.as_deref() != theme || self.by_name.len() >= ICON_CACHE_MAX_ENTRIES {
+            self.clear();
+            self.theme = theme.map(str::to_string);
+        }
+        self.by_name
+            .entry(source.to_string())
+            .or_insert_with(|| find_in_theme(source, theme).and_then(|path| load_file(&path)).map(Arc::new))
+            .clone()
+    }
+
+    /// Forgets every lookup, for icons installed or themes changed since
+    pub fn clear(&mut self) {
+        self.by_name.clear();
+    }
+}
+
+fn load_file(path: &Path) -> Option<IconImage> {
+    if path.extension().is_some_and(|ext| ext == "svg" || ext == "svgz") {
+        return load_svg(path);
+    }
+    IconImage::from_rgba(image::open(path).ok()?.to_rgba8())
+}
+
+fn load_svg(path: &Path) -> Option<IconImage> {
+    let data = fs::read(path).ok()?;
+    let tree = resvg::Tree::from_usvg(&usvg::Tree::from_data(&data, &usvg::Options::default()).ok()?);
+
+    let scale = ICON_MAX_SIZE as f32 / tree.size.width().max(tree.size.height());
+    let mut pixmap = tiny_skia::Pixmap::new(
+        (tree.size.width() * scale).ceil() as u32,
+        (tree.size.height() * scale).ceil() as u32,
+    )?;
+    tree.render(tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+    Some(IconImage::from_pixmap(pixmap))
+}
+
+/// Base directories of icon themes, by priority
+fn icon_dirs() -> Vec<PathBuf> {
+    let home = std::env::var("HOME").ok().map(PathBuf::from);
+    let data_home = std::env::var("XDG_DATA_HOME").ok().map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS").ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    home.map(|h| h.join(".icons"))
+        .into_iter()
+        .chain(data_home.map(|d| d.join("icons")))
+        .chain(data_dirs.split(':').map(|d| Path::new(d).join("icons")))
+        .collect()
+}
+
+/// The theme followed by the ones it inherits from, breadth first, hicolor last as the spec asks
+fn theme_chain(theme: Option<&str>, dirs: &[PathBuf]) -> Vec<String> {
+    let mut chain: Vec<String> = theme.map(|t| vec![t.to_string()]).unwrap_or_default();
+    let mut seen: HashSet<String> = chain.iter().cloned().collect();
+    let mut i = 0;
+    while i < chain.len() {
+        let inherits = dirs.iter()
+            .filter_map(|d| fs::read_to_string(d.join(&chain[i]).join("index.theme")).ok())
+            .find_map(|index| index.lines().find_map(|l| l.strip_prefix("Inherits=").map(str::to_string)))
+            .unwrap_or_default();
+        for parent in inherits.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if seen.insert(parent.to_string()) {
+                chain.push(parent.to_string());
+            }
+        }
+        i += 1;
+    }
+    if !seen.contains("hicolor") {
+        chain.push("hicolor".to_string());
+    }
+    chain
+}
+
+/// Nominal size from the directory names (48x48/apps, apps/48, 48x48@2/apps...), SVGs win over any PNG
+fn candidate_score(path: &Path) -> u32 {
+    if path.extension().is_some_and(|ext| ext == "svg") {
+        return u32::MAX;
+    }
+    let size = path.components()
+        .filter_map(|c| {
+            let digits: String = c.as_os_str().to_string_lossy().chars().take_while(char::is_ascii_digit).collect();
+            digits.parse::<u32>().ok()
+        })
+        .next_back()
+        .unwrap_or(0);
+    // Huge bitmaps only cost time to scale down
+    if size > 256 { 1 } else { size }
+}
+
+fn find_in_theme(name: &str, theme: Option<&str>) -> Option<PathBuf> {
+    let dirs = icon_dirs();
+    let escaped = glob::Pattern::escape(name);
+
+    for theme in theme_chain(theme, &dirs) {
+        for dir in &dirs {
+            let base = glob::Pattern::escape(&dir.join(&theme).to_string_lossy());
+            let best = ["svg", "png"].iter()
+                .filter_map(|ext| glob::glob(&format!("{base}/*/*/{escaped}.{ext}")).ok())
+                .flat_map(|paths| paths.filter_map(Result::ok))
+                .max_by_key(|p| candidate_score(p));
+            if best.is_some() {
+                return best;
+            }
+        }
+    }
+
+    ["png", "svg"].iter()
+        .map(|ext| Path::new("/usr/share/pixmaps").join(format!("{name}.{ext}")))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_data_is_decoded() {
+        // 2x1 RGB, rows padded to 8 bytes
+        let bytes = [255, 0, 0, 0, 0, 255, 0, 0];
+        let icon = decode_pixels(2, 1, 8, false, &bytes).unwrap();
+        assert_eq!((icon.width, icon.height), (2, 1));
+        assert_eq!(icon.data[..4], argb(255, 0, 0, 255));
+    }
+
+    #[test]
+    fn truncated_image_data_is_rejected() {
+        // The last row needs 6 bytes, only 5 are there
+        assert!(decode_pixels(2, 2, 6, false, &[0; 11]).is_none());
+        assert!(decode_pixels(2, 2, 5, false, &[0; 12]).is_none());
+        assert!(decode_pixels(0, 2, 6, false, &[0; 12]).is_none());
+    }
+
+    #[test]
+    fn oversized_image_data_is_rejected() {
+        assert!(decode_pixels(100_000, 100_000, 400_000, true, &[0; 16]).is_none());
+        assert!(decode_pixels(2000, 1, 8000, true, &vec![0; 8000]).is_none());
+    }
+}
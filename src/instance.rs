@@ -0,0 +1,59 @@
+// One heimdallr per Wayland display: the first instance holds an flock on a pid file,
+// the lock goes away with the process, even if it crashes.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub struct InstanceLock {
+    // Keeps the lock alive, never read
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Ok(None) means that another instance holds the lock
+    pub fn acquire(path: &Path) -> std::io::Result<Option<Self>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(path)?;
+
+        let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            return if err.kind() == std::io::ErrorKind::WouldBlock { Ok(None) } else { Err(err) };
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Some(InstanceLock { _file: file }))
+    }
+
+    /// Retries until the previous owner goes away or timeout expires
+    pub fn wait(path: &Path, timeout: Duration) -> std::io::Result<Option<Self>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(lock) = Self::acquire(path)? {
+                return Ok(Some(lock));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Pid written by the instance holding the lock, if readable
+    pub fn holder_pid(path: &Path) -> Option<u32> {
+        let mut content = String::new();
+        File::open(path).ok()?.read_to_string(&mut content).ok()?;
+        content.trim().parse().ok()
+    }
+}
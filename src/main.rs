@@ -2,9 +2,9 @@ use signal_hook::{consts::{SIGHUP, SIGINT, SIGPIPE, SIGTERM}, iterator::Signals,
 
 use serde::Deserialize;
 use smithay_client_toolkit::{
-    compositor::CompositorState, output::OutputState, registry::RegistryState, shell::wlr_layer::{Anchor, KeyboardInteractivity, Layer, LayerShell}, shm::Shm
+    activation::ActivationState, compositor::CompositorState, output::OutputState, registry::RegistryState, shell::wlr_layer::{Anchor, KeyboardInteractivity, Layer, LayerShell}, shm::Shm
 };
-use wayland_client::{Connection, EventQueue, globals::{GlobalList, registry_queue_init}, protocol::{wl_compositor, wl_output::WlOutput, wl_region}};
+use wayland_client::{Connection, EventQueue, QueueHandle, globals::{GlobalList, registry_queue_init}, protocol::{wl_compositor, wl_output::WlOutput, wl_region}};
 
 use std::{sync::mpsc::{self, Receiver, Sender}, time::{Duration}};
 
@@ -12,10 +12,11 @@ use smithay_client_toolkit::shell::WaylandSurface;
 
 use std::panic;
 use std::thread;
+use std::path::PathBuf;
 
 use colored::Colorize;
 
-use crate::{battery::{BatteryState, BatteryStats}, commands::start_command_listener, data::{BluetoothStats, IconChange, RatatoskrSocket}, notifications::Notification, security::{MicCameraStatus, start_security_monitor}, utils::{get_color_gradient, log_to_file, select_icon}};
+use crate::{battery::{BatteryState, BatteryStats}, commands::{CommandReply, CommandRequest, start_command_listener, start_socket_listener, start_wob_listener}, data::{BluetoothStats, IconChange, RatatoskrSocket}, notifications::{Notification, NotificationMessage, NotificationSignal}, security::{MicCameraStatus, start_security_monitor}, paths::{PathOverrides, RuntimePaths}, utils::{get_color_gradient, log_to_file, select_icon, set_log_path}};
 
 mod data;
 mod config;
@@ -27,20 +28,40 @@ mod config;
 mod heimdallr_layer;
 mod notifications;
 mod commands;
+mod command_parser;
+mod ctl;
 mod utils;
 mod battery;
 mod security;
 mod countdown;
 mod pills;
+mod events;
+mod paths;
+mod instance;
+mod handover;
+mod history;
+mod dnd;
+mod icons;
+mod markup;
 
 use config::Config;
+use command_parser::{Command, HistoryCommand, IconCommand, NotificationCommand, parse_command};
+
+const CONFIG_PATH: &str = "~/.config/heimdallr/config.json";
+/// How long --replace waits for the running instance to answer and then to exit
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(3);
 // use chrono;
 
-use crate::heimdallr_layer::HeimdallrLayer;
+use crate::heimdallr_layer::{HeimdallrLayer, INTERNAL_ICONS};
 use crate::notifications::start_notification_listener;
 use crate::battery::start_battery_listener;
+use crate::ctl::CtlArgs;
+use crate::config::PathsConfig;
+use crate::handover::Handover;
+use crate::history::History;
+use crate::instance::InstanceLock;
 
-use clap::{crate_name, crate_version, Parser};
+use clap::{crate_name, crate_version, Parser, Subcommand};
 
 #[derive(Debug, Parser)]
 #[command(disable_version_flag = true, about = "Zero-config system HUD for Wayland", long_about = None)]
@@ -54,6 +75,33 @@ struct Args {
 
     #[arg(short = 'V', long, help = "Print version")]
     version: bool,
+
+    #[arg(long, help = "Replace the instance running on this display, keeping timer, notifications and icons")]
+    replace: bool,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Control socket (default: $XDG_RUNTIME_DIR/heimdallr-$WAYLAND_DISPLAY.sock)")]
+    socket: Option<PathBuf>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Command FIFO (default: $XDG_RUNTIME_DIR/heimdallr-$WAYLAND_DISPLAY.cmds)")]
+    fifo: Option<PathBuf>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Ratatoskr socket (default: $XDG_RUNTIME_DIR/ratatoskr.sock, then /tmp/ratatoskr.sock)")]
+    ratatoskr_socket: Option<PathBuf>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Log file (default: $XDG_STATE_HOME/heimdallr/heimdallr.log)")]
+    log_file: Option<PathBuf>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Also read values in wob's format from this FIFO (default: disabled)")]
+    wob_fifo: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+    /// Send a command to the running instance
+    Ctl(CtlArgs),
 }
 
 fn choose_output (app: &HeimdallrLayer) -> std::option::Option<WlOutput>{
@@ -77,6 +125,221 @@ fn choose_output (app: &HeimdallrLayer) -> std::option::Option<WlOutput>{
     chosen_output
 }
 
+/// Executes a parsed command coming from the FIFO, the control socket or the demo thread.
+/// The Ok value is an optional payload sent back to socket clients.
+fn handle_command(app: &mut HeimdallrLayer, qh: &QueueHandle<HeimdallrLayer>, cmd: Command) -> Result<Option<serde_json::Value>, String> {
+    match cmd {
+        Command::Notification(NotificationCommand::Action(which)) => {
+            let key = app.invoke_notification_action(qh, &which)?;
+            Ok(Some(serde_json::json!({ "key": key })))
+        },
+        Command::Notification(NotificationCommand::Browse(offset)) => {
+            let (position, count) = app.browse_notifications(offset)?;
+            Ok(Some(serde_json::json!({ "position": position, "count": count })))
+        },
+        Command::Notification(NotificationCommand::Show(position)) => {
+            let (position, count) = app.show_notification(position)?;
+            Ok(Some(serde_json::json!({ "position": position, "count": count })))
+        },
+        Command::Notification(NotificationCommand::Hide) => {
+            if app.hide_notifications() {
+                Ok(None)
+            } else {
+                Err("No notification shown".to_string())
+            }
+        },
+        Command::Notification(NotificationCommand::Dismiss) => {
+            if app.remove_notification() {
+                app.request_redraw("hide_notification");
+                Ok(None)
+            } else {
+                Err("No notification to hide".to_string())
+            }
+        },
+        Command::Timer(spec) => {
+            match app.set_countdown(&spec) {
+                Ok(secs) => {
+                    if secs == 0 {
+                        app.remove_icon("timer");
+                    }
+                    app.request_redraw("timer set");
+                    eprintln!("Timer set to {} seconds", secs);
+                    Ok(Some(serde_json::json!({ "seconds": secs })))
+                },
+                Err(err) => Err(format!("Error setting timer: {err}"))
+            }
+        },
+        Command::Wob { value, kind, muted, color } => {
+            app.show_value(value, kind.as_deref(), muted, color);
+            Ok(None)
+        },
+        // "timr 10" is a typo, not a value of kind "timr"
+        Command::KindValue { kind, value, muted } => {
+            if !app.config.wob.kinds.contains_key(&kind) {
+                return Err(format!("Unknown command: {kind}"));
+            }
+            app.show_value(value, Some(&kind), muted, None);
+            Ok(None)
+        },
+        Command::Icon(IconCommand::Set { id, .. } | IconCommand::Clear { id }) if INTERNAL_ICONS.contains(&id.as_str()) => {
+            Err(format!("Icon id {id} is reserved by Heimdallr"))
+        },
+        Command::Icon(IconCommand::Set { id, symbol, warn, ttl, info }) => {
+            if app.set_external_icon(&id, &symbol, warn, ttl, info) != IconChange::None {
+                app.request_redraw("icon set");
+            }
+            Ok(None)
+        },
+        Command::Icon(IconCommand::Clear { id }) => {
+            if app.remove_icon(&id) {
+                app.request_redraw("icon clear");
+                Ok(None)
+            } else {
+                Err(format!("No icon with id {id}"))
+            }
+        },
+        Command::History(HistoryCommand::List(count)) => {
+            Ok(Some(app.history.list(count).iter().map(|e| e.to_json()).collect()))
+        },
+        Command::History(HistoryCommand::Search(pattern)) => {
+            Ok(Some(app.history.search(&pattern)?.iter().map(|e| e.to_json()).collect()))
+        },
+        Command::History(HistoryCommand::Show(seq)) => {
+            let id = app.replay_history(seq)?;
+            Ok(Some(serde_json::json!({ "id": id })))
+        },
+        Command::History(HistoryCommand::Clear) => {
+            app.history.clear();
+            Ok(None)
+        },
+        Command::Dnd(mode) => {
+            if let Some(mode) = mode {
+                app.set_dnd(&mode)?;
+            }
+            Ok(Some(app.dnd.to_json()))
+        },
+        Command::Query => Ok(Some(app.snapshot())),
+        Command::Subscribe(_) => Err("subscribe is only available on the control socket".to_string()),
+        Command::Reload => {
+            let config = Config::load_from_file(CONFIG_PATH);
+            log_to_file(format!("Reloaded configuration: {:?}", config));
+            if !app.ratatoskr_connected {
+                if config.hide_missing_ratatoskr { app.remove_icon("ratatoskr"); }
+                else { app.add_icon("ratatoskr", "󰠗", get_color_gradient(1.0), 1.0, None); }
+            }
+            app.history.set_config(config.history.clone());
+            app.icon_cache.clear();
+            app.config = config;
+            app.request_redraw("config reloaded");
+            Ok(None)
+        },
+        Command::Shutdown => {
+            log_to_file("Shutdown requested".to_string());
+            app.shutdown_requested = true;
+            app.history.flush();
+            serde_json::to_value(app.handover()).map(Some).map_err(|e| e.to_string())
+        },
+        Command::Demo { kind, value } => {
+            handle_demo(app, &kind, &value);
+            Ok(None)
+        }
+    }
+}
+
+/// Asks the running instance to quit and waits for its lock, returning the state it handed over
+fn take_over(paths: &RuntimePaths) -> Result<(InstanceLock, Handover), String> {
+    let reply = ctl::send_command(&paths.control_socket, "shutdown", HANDOVER_TIMEOUT)?;
+    if !reply.ok {
+        return Err(reply.error.unwrap_or_else(|| "Unknown error".to_string()));
+    }
+    let handover = match reply.data.map(serde_json::from_value::<Handover>) {
+        Some(Ok(h)) => h,
+        Some(Err(e)) => {
+            eprintln!("{}", format!("Cannot read the state of the previous instance: {e}").yellow());
+            Handover::default()
+        },
+        None => Handover::default(),
+    };
+
+    match InstanceLock::wait(&paths.lock_file, HANDOVER_TIMEOUT) {
+        Ok(Some(lock)) => Ok((lock, handover)),
+        Ok(None) => Err("The running instance did not exit in time".to_string()),
+        Err(e) => Err(format!("Cannot lock {}: {e}", paths.lock_file.display())),
+    }
+}
+
+fn handle_demo(app: &mut HeimdallrLayer, kind: &str, value: &str) {
+    match (kind, value) {
+        ("security", "on") => {
+            app.update_security_data(MicCameraStatus { mic_active: vec!["Firefox".to_string()], camera_active: vec![], pristine: true });
+            app.request_redraw("demo security on");
+        },
+        ("security", "off") => {
+            app.update_security_data(MicCameraStatus { mic_active: vec!(), camera_active: vec!(), pristine: true });
+            app.request_redraw("demo security off");
+        },
+        ("notification", text) => {
+            let notif = Notification {
+                app_name: "Demo notification".to_string(),
+                summary: "Demo notification".to_string(),
+                body: text.to_string(),
+                urgency: 1,
+                received_at: std::time::Instant::now(),
+                expired_at: Some(std::time::Instant::now() + Duration::from_secs(3)),
+                app_icon: "dialog-information".to_string(),
+                id: 0,
+                replaces_id: 0,
+                unmounting: false,
+                unmounted: false,
+                reboot: false,
+                datetime: chrono::Local::now(),
+                actions: vec![("default".to_string(), "Open".to_string()), ("reply".to_string(), "Reply".to_string())],
+                color: None,
+                image_path: String::new(),
+                icon: None,
+                progress: None,
+                count: 1
+            };
+            let _ = app.update_notification_list(Some(notif));
+            app.request_redraw("demo notification");
+        },
+        ("battery", charging_str) => {
+            if let Ok(charging) = charging_str.parse::<bool>() {
+                let bat = BatteryStats {
+                    percentage: 60.0,
+                    state: if charging { BatteryState::Charging } else { BatteryState::Discharging },
+                    eta_minutes: Some(if charging { 12.0 } else { 312.0 }),
+                    flow: Some(10.34)
+                };
+                app.update_battery_data(Some(bat));
+                app.request_redraw("demo battery");
+            }
+        },
+        ("warning-ram", w) => {
+            if let Ok(w) = w.parse::<f64>() {
+                if w > 0.3 { app.add_icon("demoram", "󰘚", get_color_gradient(w), w, None); }
+                else { app.remove_icon("demoram"); }
+            }
+        },
+        ("warning-load", w) => {
+            if let Ok(w) = w.parse::<f64>() {
+                if w > 0.3 { app.add_icon("demoload", "󰬢", get_color_gradient(w), w, None); }
+                else { app.remove_icon("demoload"); }
+            }
+        },
+        ("warning-disk", w) => {
+            if let Ok(w) = w.parse::<f64>() {
+                if w > 0.3 { app.add_icon("demodisk", "󰋊", get_color_gradient(w), w, None); }
+                else { app.remove_icon("demodisk"); }
+            }
+        },
+        // "󰞃"
+        _ => {
+            eprintln!("Unknown demo command: {} {}", kind, value);
+        }
+    }
+}
+
 fn main() {
 
     let args = Args::parse();
@@ -86,6 +349,47 @@ fn main() {
         std::process::exit(0);
     }
 
+    let overrides = PathOverrides {
+        command_fifo: args.fifo.clone(),
+        control_socket: args.socket.clone(),
+        ratatoskr_socket: args.ratatoskr_socket.clone(),
+        log_file: args.log_file.clone(),
+        wob_fifo: args.wob_fifo.clone(),
+    };
+
+    if let Some(SubCommand::Ctl(ctl_args)) = args.command {
+        let paths = RuntimePaths::resolve(&PathsConfig::load_from_file(CONFIG_PATH), &overrides);
+        std::process::exit(ctl::run(ctl_args, &paths.control_socket));
+    }
+
+    let config = Config::load_from_file(CONFIG_PATH);
+    let paths = RuntimePaths::resolve(&config.paths, &overrides);
+    if let Err(e) = paths.prepare() {
+        eprintln!("{}", format!("Cannot create runtime directories: {e}").red());
+        std::process::exit(1);
+    }
+    set_log_path(paths.log_file.clone());
+
+    let (_instance_lock, handover) = match InstanceLock::acquire(&paths.lock_file) {
+        Ok(Some(lock)) => (lock, None),
+        Ok(None) if args.replace => match take_over(&paths) {
+            Ok((lock, handover)) => (lock, Some(handover)),
+            Err(e) => {
+                eprintln!("{}", format!("Cannot replace the running instance: {e}").red());
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => {
+            let pid = InstanceLock::holder_pid(&paths.lock_file).map(|p| format!(" (pid {p})")).unwrap_or_default();
+            eprintln!("{}", format!("heimdallr is already running on this display{pid}, use --replace to take over").red());
+            std::process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("{}", format!("Cannot lock {}: {e}", paths.lock_file.display()).red());
+            std::process::exit(1);
+        }
+    };
+
     panic::set_hook(Box::new(|info| {
         eprintln!("PANIC");
         eprintln!("{info}");
@@ -127,9 +431,8 @@ fn main() {
     env_logger::init();
 
     log_to_file(format!("{} {} started", crate_name!(), crate_version!()));
-
-    let config = Config::load_from_file("~/.config/heimdallr/config.json");
     log_to_file(format!("Loaded configuration: {:?}", config));
+    log_to_file(format!("Runtime paths: {:?}", paths));
 
     let conn = Connection::connect_to_env().unwrap();
     let (globals, mut event_queue): (GlobalList, EventQueue<HeimdallrLayer>) = registry_queue_init(&conn).unwrap();
@@ -145,6 +448,9 @@ fn main() {
         config.clone()
     );
 
+    app.activation = ActivationState::bind(&globals, &qh).ok();
+    app.history = History::load(paths.history_file.clone(), config.history.clone());
+
     if !config.hide_missing_ratatoskr {
         app.add_icon("ratatoskr", "󰠗", get_color_gradient(1.0), 1.0, None);
         /* app.animator.animate_property(
@@ -179,19 +485,38 @@ fn main() {
     layer.commit();
 
     app.layer = Some(layer);
+
+    if let Some(handover) = handover {
+        log_to_file(format!("Taking over: {:?}", handover));
+        app.restore(handover);
+    }
     
     // app.add_icon("avg", "󰬢", (1.0, 0.2, 0.2, 1.0)); // example
-    let (tx, rx_cmds): (Sender<String>, Receiver<String>) = mpsc::channel();
-    let _ = start_command_listener(tx, "/tmp/heimdallr_cmds");
+    let (tx_cmds, rx_cmds): (Sender<CommandRequest>, Receiver<CommandRequest>) = mpsc::channel();
+    if let Err(e) = start_command_listener(tx_cmds.clone(), &paths.command_fifo) {
+        log_to_file(format!("Command FIFO error: {:?}", e));
+        eprintln!("{}", format!("Command FIFO error: {:?}", e).red());
+    }
+    if let Some(wob_fifo) = &paths.wob_fifo
+        && let Err(e) = start_wob_listener(tx_cmds.clone(), wob_fifo, config.wob.compat_max, config.wob.compat_kind.clone()) {
+        log_to_file(format!("wob FIFO error: {:?}", e));
+        eprintln!("{}", format!("wob FIFO error: {:?}", e).red());
+    }
+    if let Err(e) = start_socket_listener(tx_cmds.clone(), &paths.control_socket, app.events.clone()) {
+        log_to_file(format!("Control socket error: {:?}", e));
+        eprintln!("{}", format!("Control socket error: {:?}", e).red());
+    }
 
-    let mut sock = RatatoskrSocket::new("/tmp/ratatoskr.sock");
+    let mut sock = RatatoskrSocket::new(paths.ratatoskr_sockets.clone());
 
-    let (tx, rx_notif): (Sender<Notification>, Receiver<Notification>) = mpsc::channel();
+    let (tx, rx_notif): (Sender<NotificationMessage>, Receiver<NotificationMessage>) = mpsc::channel();
     // let rx_notif: Option<Receiver<Notification>> = None;
-    
+    let (tx_signals, rx_signals): (Sender<NotificationSignal>, Receiver<NotificationSignal>) = mpsc::channel();
+    app.notification_signals = Some(tx_signals);
+
     thread::spawn(|| {
         futures::executor::block_on(async {
-            if let Err(e) = start_notification_listener(tx).await {
+            if let Err(e) = start_notification_listener(tx, rx_signals).await {
                 log_to_file(format!("Notification listener error: {:?}", e));
                 let msg = format!("Notification listener error: {:?}", e).red().to_string();
                 dbg_println!("{}", msg);
@@ -219,7 +544,7 @@ fn main() {
         });
     });
 
-    let (demo_tx, demo_rx) = mpsc::channel::<(String, String)>();
+    let demo_tx = tx_cmds.clone();
 
 
     if false {
@@ -244,7 +569,11 @@ fn main() {
 
             for (kind, value, delay) in actions {
                 std::thread::sleep(delay);
-                let _ = demo_tx.send((kind.to_string(), value.to_string()));
+                let line = match kind {
+                    "wob" | "timer" => format!("{kind} {value}"),
+                    _ => format!("demo {kind} {value}")
+                };
+                let _ = demo_tx.send(CommandRequest { line, reply: None });
             }
         });
     }
@@ -275,84 +604,6 @@ fn main() {
         // Dispatch wayland events
         let _ = event_queue.dispatch_pending(&mut app);
 
-        // Dispatch demo events
-        if let Ok((kind, value)) = demo_rx.try_recv() {
-            match (kind.as_str(), value.as_str()) {
-                ("timer", time) => {
-                    app.set_countdown(&time).ok();
-                    app.request_redraw("demo timer");
-                },
-                ("security", "on") => {
-                    app.update_security_data(MicCameraStatus { mic_active: vec!["Firefox".to_string()], camera_active: vec![], pristine: true });
-                    app.request_redraw("demo security on");
-                },
-                ("security", "off") => {
-                    app.update_security_data(MicCameraStatus { mic_active: vec!(), camera_active: vec!(), pristine: true });
-                    app.request_redraw("demo security off");
-                },
-                ("wob", val) => {
-                    if let Ok(value) = val.parse::<f64>() {
-                        app.show_value(value, None);
-                    } else {
-                        eprintln!("Invalid wob value: {}", val);
-                    }
-                },
-                ("notification", text) => {
-                    let notif = Notification {
-                        app_name: "Demo notification".to_string(),
-                        summary: "Demo notification".to_string(),
-                        body: text.to_string(),
-                        urgency: 1,
-                        received_at: std::time::Instant::now(),
-                        expired_at: Some(std::time::Instant::now() + Duration::from_secs(3)),
-                        app_icon: "dialog-information".to_string(),
-                        id: 0,
-                        replaces_id: 0,
-                        unmounting: false,
-                        unmounted: false,
-                        reboot: false,
-                        datetime: chrono::Local::now()
-                    };
-                    let _ = app.update_notification_list(Some(notif));
-                    app.request_redraw("demo notification");
-                },
-                ("battery", charging_str) => {
-                    if let Ok(charging) = charging_str.parse::<bool>() {
-                        let bat = BatteryStats {
-                            percentage: 60.0,
-                            state: if charging { BatteryState::Charging } else { BatteryState::Discharging },
-                            eta_minutes: Some(if charging { 12.0 } else { 312.0 }),
-                            flow: Some(10.34)
-                        };
-                        app.update_battery_data(Some(bat));
-                        app.request_redraw("demo battery");
-                    }
-                },
-                ("warning-ram", w) => {
-                    if let Ok(w) = w.parse::<f64>() {
-                        if w > 0.3 { app.add_icon("demoram", "󰘚", get_color_gradient(w), w, None); }
-                        else { app.remove_icon("demoram"); }
-                    }
-                },
-                ("warning-load", w) => {
-                    if let Ok(w) = w.parse::<f64>() {
-                        if w > 0.3 { app.add_icon("demoload", "󰬢", get_color_gradient(w), w, None); }
-                        else { app.remove_icon("demoload"); }
-                    }
-                },
-                ("warning-disk", w) => {
-                    if let Ok(w) = w.parse::<f64>() {
-                        if w > 0.3 { app.add_icon("demodisk", "󰋊", get_color_gradient(w), w, None); }
-                        else { app.remove_icon("demodisk"); }
-                    }
-                },
-                // "󰞃"
-                _ => {
-                    eprintln!("Unknown demo command: {} {}", kind, value);
-                }
-            }
-        }
-
         if let Ok(bat) = rx_battery.try_recv() {
             app.update_battery_data(Some(bat));
             app.request_redraw(&"battery");
@@ -374,72 +625,22 @@ fn main() {
             app.request_redraw("security updated"); // TODO: in the new system, pill will know if it needs redraw, without forcing here
         }
 
-        if let Ok(cmd) = rx_cmds.try_recv() {
-            match &*cmd {
-                "hide_notification" => {
-                    println!("hide!");
-                    if app.remove_notification() {
-                        app.request_redraw("hide_notification");
-                    } else {
-                        eprintln!("--- No remove?");
-                    }
-                },
-                /* "prev_notification" => {
-                    if app.show_notification(-1) {
-                        app.request_redraw("prev_notification");
-                    }
-                }, */
-                /* "next_notification" => {
-                    if app.show_notification(1) {
-                        app.request_redraw("next_notification");
-                    }
-                }, */
-                _ => {
-                    println!("cmd to be parsed: {}", cmd);
-                    let parts: Vec<&str> = cmd.split(" ").collect();
-                    match parts.as_slice() {
-                        ["timer", value_str] => {
-                            match app.set_countdown(value_str) {
-                                Ok(secs) => {
-                                    if secs > 0 {
-                                        // app.update_timer_icon();
-                                    } else {
-                                        app.remove_icon("timer");
-                                    }
-                                    app.request_redraw("timer set");
-                                    eprintln!("Timer set to {} seconds", secs);
-                                },
-                                Err(err) => {
-                                    eprintln!("Error setting timer: {err}");
-                                }
-                            }
-                            /* match value_str.parse::<f64>() {
-                                Ok(value) => { eprintln!("Timer set to {} seconds", value); true },
-                                Err(_) => { eprintln!("Invalid number: {}", value_str); false }
-                            } */
-                        }
-                        
-                        [kind, value_str] => {
-                            match value_str.parse::<f64>() {
-                                Ok(value) => { app.show_value(value, Some(*kind)); },
-                                Err(_) => { eprintln!("Invalid number: {}", value_str); }
-                            }
-                        }
-
-                        [value_str] => {
-                            match value_str.parse::<f64>() {
-                                Ok(value) => { app.show_value(value, None); },
-                                Err(_) => { eprintln!("Invalid number: {}", value_str); }
-                            }
-                        }
-
-                        _ => {
-                            eprintln!("Unknown command");
-                        }
-                    };
-                }
-            };
-            // app.request_redraw();
+        if let Ok(request) = rx_cmds.try_recv() {
+            println!("cmd to be parsed: {}", request.line);
+            let result = parse_command(&request.line).and_then(|cmd| handle_command(&mut app, &qh, cmd));
+            if let Err(err) = &result {
+                eprintln!("Command '{}' failed: {err}", request.line);
+            }
+            // FIFO commands have nobody waiting for an answer
+            if let Some(reply) = request.reply {
+                let _ = reply.send(CommandReply::from_result(result));
+            }
+            if app.shutdown_requested {
+                // Leave the socket thread some time to deliver the reply
+                thread::sleep(Duration::from_millis(200));
+                eprintln!("Shutting down on request");
+                std::process::exit(0);
+            }
         }
         
         //println!("Ricevuto: {}", msg);
@@ -516,13 +717,13 @@ fn main() {
                 if app.ratatoskr_connected != new_ratatoskr_status {
                     app.ratatoskr_connected = new_ratatoskr_status;
                     if !new_ratatoskr_status {
-                        app.icons.clear();
+                        app.clear_ratatoskr_icons();
                         app.update_devices_data(Vec::new());
                         /* let keys: Vec<String> = app.icons.keys().cloned().collect();
                         for iconkey in keys {
                             app.remove_icon(&iconkey);
                         } */
-                        if !config.hide_missing_ratatoskr { app.add_icon("ratatoskr", "󰠗", get_color_gradient(1.0), 1.0, None); }
+                        if !app.config.hide_missing_ratatoskr { app.add_icon("ratatoskr", "󰠗", get_color_gradient(1.0), 1.0, None); }
                     } else {
                         app.remove_icon("ratatoskr");
                     }
@@ -574,13 +775,26 @@ fn main() {
             }
         }
 
-        if let Ok(new_notif) = rx_notif.try_recv() {
-            println!("{:?}", new_notif);
-            if new_notif.reboot {
-                app.add_icon("reboot", "󱄋", get_color_gradient(1.0), 1.0, None);
+        // Everything waiting, a flood must not be served one notification per tick
+        for message in rx_notif.try_iter() {
+            match message {
+                NotificationMessage::Notify(new_notif) => {
+                    println!("{:?}", new_notif);
+                    if new_notif.reboot {
+                        app.add_icon("reboot", "󱄋", get_color_gradient(1.0), 1.0, None);
+                    }
+                    app.update_notification_list(Some(*new_notif));
+                    app.request_redraw("notifications updated");
+                },
+                NotificationMessage::Close(id) => {
+                    if app.close_notification(id) {
+                        app.request_redraw("notification closed");
+                    }
+                },
+                NotificationMessage::Osd { id, value, kind } => {
+                    app.show_osd(id, value, &kind);
+                },
             }
-            app.update_notification_list(Some(new_notif));
-            app.request_redraw("notifications updated");
         }
 
         app.check_redraw_timeout();
@@ -0,0 +1,196 @@
+// Body markup of notifications: the spec allows <b>, <i>, <u>, <a href="..."> and <img src="..." alt="...">.
+// Bodies go through here before reaching Pango, which refuses anything malformed; other tags are shown as text.
+
+enum Token {
+    Text(String),
+    Open(&'static str),
+    Close(&'static str),
+    /// <img>, replaced by its alt text
+    Image(String),
+    /// <br> and <br/>, which many senders use even if the spec doesn't list it
+    LineBreak,
+}
+
+/// Tags kept as formatting, with the Pango markup they become
+const FORMATTING: &[(&str, &str, &str)] = &[
+    ("b", "<b>", "</b>"),
+    ("i", "<i>", "</i>"),
+    ("u", "<u>", "</u>"),
+    // Links can't be clicked, they are only underlined
+    ("a", "<span underline=\"single\">", "</span>"),
+];
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))?
+                .ok()?;
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Text with entities decoded; an '&' that doesn't start an entity is kept as it is
+fn decode_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = rest.find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Value of attribute `name` in the inside of a tag, quoted or not
+fn attribute(inner: &str, name: &str) -> Option<String> {
+    let lower = inner.to_ascii_lowercase();
+    let mut search = 0;
+    while let Some(found) = lower[search..].find(name) {
+        let start = search + found;
+        search = start + name.len();
+        let preceded_by_space = lower[..start].ends_with(|c: char| c.is_whitespace());
+        let after = inner[search..].trim_start();
+        if !preceded_by_space || !after.starts_with('=') {
+            continue;
+        }
+        let value = after[1..].trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+            _ => value.split(|c: char| c.is_whitespace() || c == '/').next(),
+        };
+        return Some(decode_text(value.unwrap_or_default()));
+    }
+    None
+}
+
+/// The token for the inside of <...>, None if it is not an allowed tag
+fn parse_tag(inner: &str) -> Option<Token> {
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, inner),
+    };
+    let name = inner.split(|c: char| c.is_whitespace() || c == '/').next()?.to_ascii_lowercase();
+
+    if name == "br" {
+        return Some(Token::LineBreak);
+    }
+    if name == "img" && !closing {
+        return Some(Token::Image(attribute(inner, "alt").unwrap_or_default()));
+    }
+    let (tag, _, _) = FORMATTING.iter().find(|(tag, _, _)| *tag == name)?;
+    Some(if closing { Token::Close(tag) } else { Token::Open(tag) })
+}
+
+fn tokenize(body: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut text = String::new();
+    let mut rest = body;
+
+    while let Some(pos) = rest.find('<') {
+        text.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        match rest.find('>').and_then(|end| parse_tag(&rest[1..end]).map(|t| (t, end))) {
+            Some((token, end)) => {
+                if !text.is_empty() {
+                    tokens.push(Token::Text(decode_text(&std::mem::take(&mut text))));
+                }
+                tokens.push(token);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('<');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        tokens.push(Token::Text(decode_text(&text)));
+    }
+    tokens
+}
+
+fn pango_tags(tag: &str) -> (&'static str, &'static str) {
+    FORMATTING.iter()
+        .find(|(name, _, _)| *name == tag)
+        .map(|(_, open, close)| (*open, *close))
+        .unwrap_or(("", ""))
+}
+
+/// Pango markup for a notification body: allowed tags are converted and balanced, everything else is escaped
+pub fn to_pango(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut open: Vec<&'static str> = vec![];
+
+    for token in tokenize(body) {
+        match token {
+            Token::Text(text) | Token::Image(text) => out.push_str(&escape(&text)),
+            Token::LineBreak => out.push('\n'),
+            Token::Open(tag) => {
+                out.push_str(pango_tags(tag).0);
+                open.push(tag);
+            }
+            // A close without its open is dropped; one closing an outer tag closes the inner ones too
+            Token::Close(tag) => {
+                if let Some(pos) = open.iter().rposition(|t| *t == tag) {
+                    for inner in open.drain(pos..).rev() {
+                        out.push_str(pango_tags(inner).1);
+                    }
+                }
+            }
+        }
+    }
+    for tag in open.into_iter().rev() {
+        out.push_str(pango_tags(tag).1);
+    }
+    out
+}
+
+/// The body as plain text, for rules, the history and anything else that doesn't draw it
+pub fn strip(body: &str) -> String {
+    tokenize(body).into_iter()
+        .filter_map(|token| match token {
+            Token::Text(text) | Token::Image(text) => Some(text),
+            Token::LineBreak => Some("\n".to_string()),
+            Token::Open(_) | Token::Close(_) => None,
+        })
+        .collect()
+}
+
+/// Escapes text to be put in Pango markup
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
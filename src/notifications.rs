@@ -1,14 +1,295 @@
 use chrono::Local;
-use zbus::{ConnectionBuilder, dbus_interface, zvariant};
-use std::{collections::HashMap, sync::mpsc::Sender, time::{Duration, Instant}};
+use serde::Serialize;
+use zbus::{ConnectionBuilder, SignalContext, dbus_interface, zvariant};
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, mpsc::{Receiver, Sender}}, time::{Duration, Instant}};
 use std::sync::atomic::{AtomicU32, Ordering};
 
+use crate::config::{FloodConfig, NotificationRule, RuleEffects};
+use crate::icons::{self, IconCache, IconImage};
+use crate::markup;
 use crate::utils::log_to_file;
 
 static NEXT_ID: AtomicU32 = AtomicU32::new(2);
 
-fn generate_id() -> u32 {
-    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+pub(crate) fn generate_id() -> u32 {
+    NEXT_ID.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| Some(following_id(next)))
+        .unwrap_or_else(|next| next)
+}
+
+/// 0 means "no id" in the spec, the counter wraps to 1
+fn following_id(id: u32) -> u32 {
+    id.checked_add(1).unwrap_or(1)
+}
+
+/// Next id to hand out once up_to is taken; a client can send any replaces_id, u32::MAX included
+fn next_after_reserving(next: u32, up_to: u32) -> u32 {
+    match up_to.checked_add(1) {
+        Some(after) => next.max(after),
+        None if next == u32::MAX => 1,
+        None => next,
+    }
+}
+
+/// The spec wants a replacement to keep the id it replaces, so the sender can update it again
+fn notification_id(replaces_id: u32) -> u32 {
+    if replaces_id > 0 {
+        reserve_ids(replaces_id);
+        replaces_id
+    } else {
+        generate_id()
+    }
+}
+
+/// Makes sure new ids don't clash with notifications inherited from a previous instance
+pub fn reserve_ids(up_to: u32) {
+    let _ = NEXT_ID.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| Some(next_after_reserving(next, up_to)));
+}
+
+/// Why a notification left the queue
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    Expired,
+    Dismissed,
+    /// The sender called CloseNotification
+    Closed,
+    Replaced,
+    /// Dropped, merged or sent to the history without ever entering the queue
+    NotShown,
+}
+
+impl CloseReason {
+    /// Reason code of the NotificationClosed signal
+    pub fn code(&self) -> u32 {
+        match self {
+            CloseReason::Expired => 1,
+            CloseReason::Dismissed => 2,
+            CloseReason::Closed => 3,
+            // "Undefined/reserved reasons", the spec has nothing better
+            CloseReason::Replaced | CloseReason::NotShown => 4,
+        }
+    }
+}
+
+/// Result of insert_notification
+#[derive(Debug, PartialEq)]
+pub struct InsertOutcome {
+    /// The notification took the place of the one with the same id, keeping its position in the queue
+    pub updated_in_place: bool,
+    /// Ids of notifications removed to make room for the new one
+    pub replaced: Vec<u32>,
+}
+
+/// Adds a notification to the queue, handling replaces_id and the udisks "Unmounting" → "Unmounted" pair.
+/// The queue keeps notifications that expire first in front, persistent ones at the end.
+pub fn insert_notification(queue: &mut Vec<Notification>, new_notif: Notification) -> InsertOutcome {
+    let mut replaced = vec![];
+
+    // "Unmounted" comes with a new id, but it must hide the critical "Unmounting" one
+    if new_notif.unmounted
+        && let Some(pos) = queue.iter().position(|n| n.unmounting) {
+        replaced.push(queue.remove(pos).id);
+    }
+
+    if new_notif.replaces_id > 0
+        && let Some(existing) = queue.iter_mut().find(|n| n.id == new_notif.replaces_id) {
+        // Not sorted again: if it is the one shown, it stays shown without entering again
+        *existing = new_notif;
+        return InsertOutcome { updated_in_place: true, replaced };
+    }
+
+    queue.insert(0, new_notif);
+    queue.sort_by_key(|item| {
+        // false comes before true, so Some items (notifications with expiration) go to the front, None (notif. without expiration) to the back.
+        // Within Some, notifications are sorted chronologically (oldest notification first).
+        (item.expired_at.is_none(), item.expired_at)
+    });
+    InsertOutcome { updated_in_place: false, replaced }
+}
+
+/// Collapses the new notification with the newest one in the queue when both come from the same app
+/// (and have the same summary, with `same_summary`): the new one takes its place with the count increased.
+/// Returns the id of the notification taken out of the queue.
+pub fn collapse_into_group(queue: &mut Vec<Notification>, new_notif: &mut Notification, same_summary: bool) -> Option<u32> {
+    // Updates and progress bars have their own life, mixing urgencies would break the order of the queue
+    if new_notif.replaces_id > 0 || new_notif.progress.is_some() {
+        return None;
+    }
+    let (pos, newest) = queue.iter().enumerate().max_by_key(|(_, n)| n.received_at)?;
+    let groupable = newest.app_name == new_notif.app_name
+        && newest.urgency == new_notif.urgency
+        && newest.progress.is_none()
+        && (!same_summary || newest.summary == new_notif.summary);
+    if !groupable {
+        return None;
+    }
+    let old = queue.remove(pos);
+    new_notif.count += old.count;
+    Some(old.id)
+}
+
+/// Notifications seen lately, to drop the copies some apps send more than once
+#[derive(Debug, Default)]
+pub struct RecentNotifications {
+    /// (app name, summary, body, received), oldest first
+    seen: VecDeque<(String, String, String, Instant)>,
+}
+
+impl RecentNotifications {
+    /// Remembers the notification, returns true if an identical one arrived less than `window` ago
+    pub fn is_duplicate(&mut self, notif: &Notification, window: Duration) -> bool {
+        let now = Instant::now();
+        while self.seen.front().is_some_and(|(_, _, _, at)| now.saturating_duration_since(*at) > window) {
+            self.seen.pop_front();
+        }
+        let duplicate = self.seen.iter().any(|(app_name, summary, body, _)| {
+            *app_name == notif.app_name && *summary == notif.summary && *body == notif.body
+        });
+        if !duplicate {
+            self.seen.push_back((notif.app_name.clone(), notif.summary.clone(), notif.body.clone(), now));
+        }
+        duplicate
+    }
+}
+
+/// Per-app token buckets, and the "42 more from X" notification of each app over its limit
+#[derive(Debug, Default)]
+pub struct FloodGuard {
+    /// Tokens left and when they were counted, by app name
+    buckets: HashMap<String, (f64, Instant)>,
+    /// Id of the summary notification and how many notifications it stands for, by app name
+    summaries: HashMap<String, (u32, u32)>,
+}
+
+impl FloodGuard {
+    /// Takes a token from the bucket of the app, false if it is empty
+    pub fn allow(&mut self, app_name: &str, config: &FloodConfig, now: Instant) -> bool {
+        if config.per_second <= 0.0 {
+            return true;
+        }
+        let (tokens, last) = self.buckets.entry(app_name.to_string()).or_insert((config.burst, now));
+        *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * config.per_second).min(config.burst);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Counts a notification over the limit in the summary of its app, which is added again if it left the queue
+    pub fn merge(&mut self, queue: &mut Vec<Notification>, notif: &Notification, icons: &mut IconCache, icon_theme: Option<&str>) {
+        let (id, merged) = self.summaries.entry(notif.app_name.clone()).or_insert((0, 0));
+        let expired_at = Some(Instant::now() + Duration::from_secs(3));
+
+        if let Some(summary) = queue.iter_mut().find(|n| n.id == *id) {
+            *merged += 1;
+            summary.summary = format!("{merged} more from {}", notif.app_name);
+            summary.expired_at = expired_at;
+            return;
+        }
+
+        *id = generate_id();
+        *merged = 1;
+        // Low urgency, so that collapse_into_group keeps it apart from the notifications of the app
+        let mut summary = Notification {
+            summary: format!("1 more from {}", notif.app_name),
+            body: String::new(),
+            urgency: 0,
+            received_at: Instant::now(),
+            expired_at,
+            id: *id,
+            unmounting: false,
+            reboot: false,
+            replaces_id: 0,
+            unmounted: false,
+            datetime: Local::now(),
+            actions: vec![],
+            progress: None,
+            count: 1,
+            ..notif.clone()
+        };
+        summary.load_icon(icons, icon_theme);
+        insert_notification(queue, summary);
+    }
+}
+
+/// Drops notifications beyond `max`, non-critical ones first, oldest first. Returns their ids.
+pub fn enforce_queue_cap(queue: &mut Vec<Notification>, max: usize) -> Vec<u32> {
+    let mut removed = vec![];
+    while queue.len() > max {
+        let Some(pos) = queue.iter()
+            .enumerate()
+            .min_by_key(|(_, n)| (n.urgency == 2, n.received_at))
+            .map(|(pos, _)| pos) else { break; };
+        removed.push(queue.remove(pos).id);
+    }
+    removed
+}
+
+/// What the rules in the config decided for a notification
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleVerdict {
+    Show,
+    HistoryOnly,
+    Drop,
+}
+
+/// Applies every rule matching the notification as it was sent, in order
+pub fn apply_rules(rules: &[NotificationRule], notif: &mut Notification) -> RuleVerdict {
+    let body = markup::strip(&notif.body);
+    let matching: Vec<&RuleEffects> = rules.iter()
+        .filter(|rule| rule.matches(&notif.app_name, &notif.summary, &body, notif.urgency))
+        .map(|rule| &rule.effects)
+        .collect();
+
+    let mut verdict = RuleVerdict::Show;
+    for effects in matching {
+        if effects.drop {
+            return RuleVerdict::Drop;
+        }
+        if effects.history_only {
+            verdict = RuleVerdict::HistoryOnly;
+        }
+        if let Some(urgency) = effects.urgency && urgency != notif.urgency {
+            notif.urgency = urgency;
+            // Same defaults as Notify: critical ones stay, the others get 3 seconds
+            notif.expired_at = if urgency == 2 { None } else { notif.expired_at.or(Some(notif.received_at + Duration::from_secs(3))) };
+        }
+        if let Some(timeout) = effects.timeout {
+            notif.expired_at = timeout.map(|t| notif.received_at + t);
+        }
+        if let Some(app_name) = &effects.app_name { notif.app_name = app_name.clone(); }
+        if let Some(app_icon) = &effects.app_icon {
+            // The icon named by the rule wins over the ones sent by the app
+            notif.app_icon = app_icon.clone();
+            notif.image_path.clear();
+            notif.icon = None;
+        }
+        if effects.color.is_some() { notif.color = effects.color; }
+    }
+    verdict
+}
+
+/// From the D-Bus server to the main loop
+#[derive(Debug)]
+pub enum NotificationMessage {
+    Notify(Box<Notification>),
+    Close(u32),
+    /// Volume/brightness popups sent as notifications, shown by the wob instead; value is a fraction (1.0 = 100%)
+    Osd { id: u32, value: f64, kind: String },
+}
+
+/// From the main loop to the D-Bus server, which turns them into signals
+#[derive(Debug, Clone)]
+pub enum NotificationSignal {
+    Closed { id: u32, reason: CloseReason },
+    ActionInvoked { id: u32, key: String },
+    /// Lets the app raise its window, must come before the matching ActionInvoked
+    ActivationToken { id: u32, token: String },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -17,7 +298,6 @@ pub struct Notification {
     pub summary: String,
     pub body: String,
     pub urgency: u8,
-    #[allow(unused)]
     pub received_at: Instant,
     pub expired_at: Option<Instant>,
     #[allow(unused)]
@@ -27,13 +307,90 @@ pub struct Notification {
     pub reboot: bool,
     pub replaces_id: u32,
     pub unmounted: bool,
-    pub datetime: chrono::DateTime<chrono::Local>
+    pub datetime: chrono::DateTime<chrono::Local>,
+    /// (key, label) pairs; the "default" key is what clicking the notification would do
+    pub actions: Vec<(String, String)>,
+    /// Color of the app name, set by the rules in the config
+    pub color: Option<(f64, f64, f64, f64)>,
+    /// From the image-path hint, wins over app_icon; empty if missing
+    pub image_path: String,
+    /// Decoded from image-data, or loaded from image_path/app_icon once the notification reaches the main loop
+    pub icon: Option<Arc<IconImage>>,
+    /// Percentage from the "value" hint, drawn as a progress bar
+    pub progress: Option<u8>,
+    /// How many notifications in a row this one stands for, see collapse_into_group
+    pub count: u32
+}
+
+impl Notification {
+    /// Unless image-data brought one, the icon comes from image-path, then from app_icon
+    pub fn load_icon(&mut self, icons: &mut IconCache, theme: Option<&str>) {
+        if self.icon.is_none() {
+            let source = if self.image_path.is_empty() { &self.app_icon } else { &self.image_path };
+            self.icon = icons.load(source, theme);
+        }
+    }
+
+    /// Time since it was received: "now", "5 min ago", "2 h ago", "3 d ago"
+    pub fn age_text(&self, now: chrono::DateTime<chrono::Local>) -> String {
+        let minutes = (now - self.datetime).num_minutes();
+        match minutes {
+            ..1 => "now".to_string(),
+            1..60 => format!("{minutes} min ago"),
+            60..1440 => format!("{} h ago", minutes / 60),
+            _ => format!("{} d ago", minutes / 1440),
+        }
+    }
+
+    /// Representation used by the `query` command
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "app_name": self.app_name,
+            "app_icon": self.app_icon,
+            "summary": self.summary,
+            "body": self.body,
+            "urgency": self.urgency,
+            "progress": self.progress,
+            "count": self.count,
+            "received": self.datetime.to_rfc3339(),
+            "expires_in_ms": self.expired_at.map(|exp| exp.saturating_duration_since(Instant::now()).as_millis() as u64),
+            "actions": self.actions.iter().map(|(key, label)| serde_json::json!({ "key": key, "label": label })).collect::<Vec<_>>()
+        })
+    }
+
+    /// Actions shown as labels, numbered from 1 by `notification action <n>`
+    pub fn visible_actions(&self) -> impl Iterator<Item = &(String, String)> {
+        self.actions.iter().filter(|(key, _)| key != "default")
+    }
+
+    /// Resolves "default", a 1-based index among visible actions, or a raw action key
+    pub fn find_action(&self, which: &str) -> Option<&str> {
+        if let Ok(idx) = which.parse::<usize>() {
+            return self.visible_actions().nth(idx.checked_sub(1)?).map(|(key, _)| key.as_str());
+        }
+        self.actions.iter().find(|(key, _)| key == which).map(|(key, _)| key.as_str())
+    }
 }
 
 #[derive(Clone)]
 struct NotificationServer {
     // notifications: Arc<Mutex<Vec<Notification>>>,
-    tx: Sender<Notification>
+    tx: Sender<NotificationMessage>
+}
+
+/// Integer hints come as any integer type, "int:value:42" from notify-send is an i32
+fn get_int(map: &HashMap<String, zvariant::Value<'_>>, key: &str) -> Option<i64> {
+    map.get(key).and_then(|v| match v {
+        zvariant::Value::U8(n) => Some(*n as i64),
+        zvariant::Value::I16(n) => Some(*n as i64),
+        zvariant::Value::U16(n) => Some(*n as i64),
+        zvariant::Value::I32(n) => Some(*n as i64),
+        zvariant::Value::U32(n) => Some(*n as i64),
+        zvariant::Value::I64(n) => Some(*n),
+        zvariant::Value::U64(n) => i64::try_from(*n).ok(),
+        _ => None,
+    })
 }
 
 fn get_u8(map: &HashMap<String, zvariant::Value<'_>>, key: &str) -> u8 {
@@ -46,6 +403,14 @@ fn get_u8(map: &HashMap<String, zvariant::Value<'_>>, key: &str) -> u8 {
         .unwrap_or(0)
 }
 
+/// Tag of OSD-style notifications, which replace each other instead of queueing
+fn osd_tag(map: &HashMap<String, zvariant::Value<'_>>) -> Option<String> {
+    ["x-canonical-private-synchronous", "x-dunst-stack-tag"].iter()
+        .find_map(|key| map.get(*key).and_then(|v| v.downcast_ref::<str>()))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+}
 
 #[dbus_interface(name = "org.freedesktop.Notifications")]
 impl NotificationServer {
@@ -56,7 +421,7 @@ impl NotificationServer {
         app_icon: &str,
         summary: &str,
         body: &str,
-        _actions: Vec<String>,
+        actions: Vec<String>,
         hints: std::collections::HashMap<String, zvariant::Value<'_>>,
         expire_timeout: i32,
     ) -> u32 {
@@ -65,14 +430,24 @@ impl NotificationServer {
 
         // let u = hints.get("urgency").and_then(Value::as_u64).and_then(|n|u8::try_from(n).ok()).unwrap_or(0);
         // let urgency = hints.get("urgency").unwrap().clone().downcast().expect("No urgency");
+        // A tag and a value make an OSD: it goes to the wob, with the tag as kind ("volume", "brightness"...)
+        if let (Some(kind), Some(value)) = (osd_tag(&hints), get_int(&hints, "value")) {
+            let id = notification_id(replaces_id);
+            let _ = self.tx.send(NotificationMessage::Osd { id, value: value.max(0) as f64 / 100.0, kind });
+            return id;
+        }
+
         let urgency: u8 = get_u8(&hints, "urgency");
-        let expired_at = if expire_timeout > 1 { Some(Instant::now() + Duration::new(expire_timeout as u64, 0)) } 
+        let progress = get_int(&hints, "value").map(|v| v.clamp(0, 100) as u8);
+        // Progress notifications stay until they reach 100% or their app closes them
+        let expired_at = if progress.is_some_and(|p| p < 100) { None }
+                                else if expire_timeout > 1 { Some(Instant::now() + Duration::new(expire_timeout as u64, 0)) }
                                 else if urgency < 2 { Some(Instant::now() + Duration::new(3, 0)) }
                                 else { None };
         // *list = list.iter().filter(|notif| notif.expired_at > Instant::now()).map(|item|item.to_owned()).collect();
         let unmounting = app_icon == "media-removable" && urgency == 2;
         let unmounted = app_icon == "media-removable" && urgency != 2;
-        let id = generate_id();
+        let id = notification_id(replaces_id);
         let new_notif = Notification {
             app_name: app_name.into(),
             summary: summary.into(),
@@ -86,9 +461,20 @@ impl NotificationServer {
             unmounting, // : summary.contains("Unmounting"),
             unmounted,
             reboot: summary.contains("Reboot recommended"),
-            datetime: Local::now()
+            datetime: Local::now(),
+            actions: actions.chunks_exact(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect(),
+            color: None,
+            // "image_data" and "icon_data" are the names used by older versions of the spec
+            image_path: ["image-path", "image_path"].iter()
+                .find_map(|key| hints.get(*key).and_then(|v| v.downcast_ref::<str>()).map(str::to_string))
+                .unwrap_or_default(),
+            icon: ["image-data", "image_data", "icon_data"].iter()
+                .find_map(|key| hints.get(*key).and_then(icons::from_image_data))
+                .map(Arc::new),
+            progress,
+            count: 1
         };
-        let _ = self.tx.send(new_notif);
+        let _ = self.tx.send(NotificationMessage::Notify(Box::new(new_notif)));
 
         
 
@@ -103,6 +489,24 @@ impl NotificationServer {
         id
     }
 
+    fn close_notification(&self, id: u32) {
+        log_to_file(format!("CloseNotification id:{id}"));
+        let _ = self.tx.send(NotificationMessage::Close(id));
+    }
+
+    fn get_capabilities(&self) -> Vec<String> {
+        vec!["body".to_string(), "actions".to_string(), "body-markup".to_string(), "icon-static".to_string()]
+    }
+
+    #[dbus_interface(signal)]
+    async fn notification_closed(ctxt: &SignalContext<'_>, id: u32, reason: u32) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn action_invoked(ctxt: &SignalContext<'_>, id: u32, action_key: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn activation_token(ctxt: &SignalContext<'_>, id: u32, activation_token: &str) -> zbus::Result<()>;
+
     fn get_server_information(&self) -> (String, String, String, String) {
         (
             "Heimdallr".to_string(),
@@ -113,20 +517,220 @@ impl NotificationServer {
     }
 }
 
-pub async fn start_notification_listener(tx: Sender<Notification>) -> zbus::Result<()> {
+pub async fn start_notification_listener(tx: Sender<NotificationMessage>, rx_signals: Receiver<NotificationSignal>) -> zbus::Result<()> {
     let server = NotificationServer {
         // notifications: Arc::new(Mutex::new(vec![])),
         tx
     };
 
-    let _conn = ConnectionBuilder::session()?
+    let conn = ConnectionBuilder::session()?
         .name("org.freedesktop.Notifications")?
-        .serve_at("/org/freedesktop/Notifications", server)?
+        .serve_at(NOTIFICATIONS_PATH, server)?
         .build()
         .await?;
 
     println!("Heimdallr is now listening to notifications!");
-    loop {
-        std::thread::park();
+
+    // Method calls are served by the zbus executor, this thread only sends signals
+    let ctxt = SignalContext::new(&conn, NOTIFICATIONS_PATH)?;
+    for signal in rx_signals.iter() {
+        let result = match &signal {
+            NotificationSignal::Closed { id, reason } => NotificationServer::notification_closed(&ctxt, *id, reason.code()).await,
+            NotificationSignal::ActionInvoked { id, key } => NotificationServer::action_invoked(&ctxt, *id, key).await,
+            NotificationSignal::ActivationToken { id, token } => NotificationServer::activation_token(&ctxt, *id, token).await,
+        };
+        if let Err(e) = result {
+            log_to_file(format!("Cannot emit {:?}: {:?}", signal, e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notif(id: u32, urgency: u8, app_icon: &str) -> Notification {
+        Notification {
+            app_name: "test".to_string(),
+            summary: format!("summary {id}"),
+            body: String::new(),
+            urgency,
+            received_at: Instant::now(),
+            expired_at: if urgency < 2 { Some(Instant::now() + Duration::from_secs(3)) } else { None },
+            app_icon: app_icon.to_string(),
+            id,
+            unmounting: app_icon == "media-removable" && urgency == 2,
+            reboot: false,
+            replaces_id: 0,
+            unmounted: app_icon == "media-removable" && urgency != 2,
+            datetime: Local::now(),
+            actions: vec![],
+            color: None,
+            image_path: String::new(),
+            icon: None,
+            progress: None,
+            count: 1,
+        }
+    }
+
+    /// Expires before anything built by notif()
+    fn expiring_soon(id: u32) -> Notification {
+        let mut n = notif(id, 1, "mail");
+        n.expired_at = Some(Instant::now() + Duration::from_secs(1));
+        n
+    }
+
+    fn ids(queue: &[Notification]) -> Vec<u32> {
+        queue.iter().map(|n| n.id).collect()
+    }
+
+    #[test]
+    fn unmounted_replaces_unmounting() {
+        let mut queue = vec![notif(5, 2, "media-removable")];
+        let outcome = insert_notification(&mut queue, notif(6, 1, "media-removable"));
+
+        assert_eq!(outcome, InsertOutcome { updated_in_place: false, replaced: vec![5] });
+        assert_eq!(ids(&queue), vec![6]);
+    }
+
+    #[test]
+    fn unmounted_replaces_unmounting_among_others() {
+        let mut queue = vec![expiring_soon(3), notif(5, 2, "media-removable"), notif(4, 2, "battery")];
+        let outcome = insert_notification(&mut queue, notif(6, 1, "media-removable"));
+
+        assert_eq!(outcome.replaced, vec![5]);
+        assert_eq!(ids(&queue), vec![3, 6, 4]);
+    }
+
+    #[test]
+    fn unmounted_without_unmounting_is_just_added() {
+        let mut queue = vec![expiring_soon(3)];
+        let outcome = insert_notification(&mut queue, notif(6, 1, "media-removable"));
+
+        assert_eq!(outcome, InsertOutcome { updated_in_place: false, replaced: vec![] });
+        assert_eq!(ids(&queue), vec![3, 6]);
+    }
+
+    #[test]
+    fn unmounting_is_kept_by_other_notifications() {
+        let mut queue = vec![notif(5, 2, "media-removable")];
+        let outcome = insert_notification(&mut queue, notif(6, 1, "mail"));
+
+        assert!(outcome.replaced.is_empty());
+        assert_eq!(ids(&queue), vec![6, 5]);
+    }
+
+    #[test]
+    fn replaces_id_updates_in_place() {
+        let mut queue = vec![notif(3, 1, "mail"), notif(4, 1, "transfer")];
+        let mut update = notif(4, 1, "transfer");
+        update.replaces_id = 4;
+        update.summary = "50%".to_string();
+        update.expired_at = Some(Instant::now() + Duration::from_secs(60));
+
+        let outcome = insert_notification(&mut queue, update);
+
+        assert_eq!(outcome, InsertOutcome { updated_in_place: true, replaced: vec![] });
+        assert_eq!(ids(&queue), vec![3, 4]);
+        assert_eq!(queue[1].summary, "50%");
+    }
+
+    #[test]
+    fn replaces_id_of_a_gone_notification_is_added() {
+        let mut queue = vec![expiring_soon(3)];
+        let mut update = notif(9, 1, "transfer");
+        update.replaces_id = 9;
+
+        let outcome = insert_notification(&mut queue, update);
+
+        assert!(!outcome.updated_in_place);
+        assert_eq!(ids(&queue), vec![3, 9]);
+    }
+
+    #[test]
+    fn same_app_in_a_row_is_collapsed() {
+        let mut queue = vec![notif(3, 1, "chat")];
+        let mut next = notif(4, 1, "chat");
+        next.received_at = Instant::now() + Duration::from_millis(1);
+
+        assert_eq!(collapse_into_group(&mut queue, &mut next, false), Some(3));
+        assert!(queue.is_empty());
+        assert_eq!(next.count, 2);
+    }
+
+    #[test]
+    fn only_the_newest_is_collapsed() {
+        let mut other = notif(4, 1, "mail");
+        other.app_name = "mail".to_string();
+        other.received_at = Instant::now() + Duration::from_millis(1);
+        let mut queue = vec![notif(3, 1, "chat"), other];
+
+        assert_eq!(collapse_into_group(&mut queue, &mut notif(5, 1, "chat"), false), None);
+        assert_eq!(ids(&queue), vec![3, 4]);
+    }
+
+    #[test]
+    fn same_summary_keeps_different_chats_apart() {
+        let mut queue = vec![notif(3, 1, "chat")];
+        // notif() puts the id in the summary
+        assert_eq!(collapse_into_group(&mut queue, &mut notif(4, 1, "chat"), true), None);
+        assert_eq!(ids(&queue), vec![3]);
+    }
+
+    #[test]
+    fn duplicates_are_detected_within_the_window() {
+        let mut recent = RecentNotifications::default();
+        let window = Duration::from_secs(10);
+
+        assert!(!recent.is_duplicate(&notif(3, 1, "chat"), window));
+        assert!(recent.is_duplicate(&notif(3, 1, "chat"), window));
+        assert!(!recent.is_duplicate(&notif(4, 1, "chat"), window));
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let config = FloodConfig { per_second: 1.0, burst: 2.0, max_queue: 50 };
+        let mut guard = FloodGuard::default();
+        let now = Instant::now();
+
+        assert!(guard.allow("chat", &config, now));
+        assert!(guard.allow("chat", &config, now));
+        assert!(!guard.allow("chat", &config, now));
+        assert!(guard.allow("mail", &config, now));
+        assert!(guard.allow("chat", &config, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn queue_cap_drops_oldest_non_critical() {
+        let now = Instant::now();
+        let mut queue: Vec<Notification> = [(1, 2), (2, 1), (3, 1)].iter()
+            .map(|&(id, urgency)| {
+                let mut n = notif(id, urgency, "mail");
+                n.received_at = now + Duration::from_millis(id as u64);
+                n
+            })
+            .collect();
+
+        assert_eq!(enforce_queue_cap(&mut queue, 2), vec![2]);
+        assert_eq!(enforce_queue_cap(&mut queue, 1), vec![3]);
+        assert_eq!(ids(&queue), vec![1]);
+    }
+
+    #[test]
+    fn replacement_keeps_the_id() {
+        assert_eq!(notification_id(42), 42);
+        let fresh = notification_id(0);
+        assert!(fresh > 42);
+    }
+
+    #[test]
+    fn ids_wrap_without_zero() {
+        assert_eq!(following_id(7), 8);
+        assert_eq!(following_id(u32::MAX), 1);
+        assert_eq!(next_after_reserving(7, 41), 42);
+        assert_eq!(next_after_reserving(50, 41), 50);
+        assert_eq!(next_after_reserving(7, u32::MAX), 7);
+        assert_eq!(next_after_reserving(u32::MAX, u32::MAX), 1);
     }
 }
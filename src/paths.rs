@@ -0,0 +1,136 @@
+// Where heimdallr puts its sockets, FIFOs and logs.
+// Defaults live in $XDG_RUNTIME_DIR / $XDG_STATE_HOME, the config file and the command line can override them.
+
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::config::PathsConfig;
+
+/// Used when Ratatoskr doesn't live in the runtime dir (older versions)
+const LEGACY_RATATOSKR_SOCKET: &str = "/tmp/ratatoskr.sock";
+
+#[derive(Debug, Clone, Default)]
+pub struct PathOverrides {
+    pub command_fifo: Option<PathBuf>,
+    pub control_socket: Option<PathBuf>,
+    pub ratatoskr_socket: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    pub wob_fifo: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimePaths {
+    pub command_fifo: PathBuf,
+    pub control_socket: PathBuf,
+    /// Tried in order until one accepts the connection
+    pub ratatoskr_sockets: Vec<PathBuf>,
+    pub log_file: PathBuf,
+    /// wob-compatible input, disabled unless configured
+    pub wob_fifo: Option<PathBuf>,
+    /// flock'ed by the running instance, see instance.rs
+    pub lock_file: PathBuf,
+    pub state_dir: PathBuf,
+    /// Per display like the sockets: instances rewrite it from memory, a shared file would lose entries
+    pub history_file: PathBuf,
+}
+
+fn uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+}
+
+/// Creates a directory readable only by us, refusing symlinks and directories owned by somebody else
+pub fn ensure_private_dir(path: &Path) -> std::io::Result<()> {
+    if !path.exists() {
+        fs::create_dir_all(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o700))?;
+    }
+
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() || !meta.is_dir() {
+        return Err(std::io::Error::other(format!("{} is not a directory", path.display())));
+    }
+    if meta.uid() != uid() {
+        return Err(std::io::Error::other(format!("{} is owned by another user", path.display())));
+    }
+    Ok(())
+}
+
+fn runtime_dir() -> PathBuf {
+    env_dir("XDG_RUNTIME_DIR").unwrap_or_else(|| PathBuf::from(format!("/tmp/heimdallr-{}", uid())))
+}
+
+fn state_dir() -> PathBuf {
+    env_dir("XDG_STATE_HOME")
+        .unwrap_or_else(|| PathBuf::from(shellexpand::tilde("~/.local/state").as_ref()))
+        .join("heimdallr")
+}
+
+/// "wayland-1" for WAYLAND_DISPLAY=wayland-1 or =/run/user/1000/wayland-1, so nested sessions get their own sockets
+fn display_suffix() -> String {
+    std::env::var("WAYLAND_DISPLAY")
+        .ok()
+        .and_then(|d| Path::new(&d).file_name().map(|f| f.to_string_lossy().into_owned()))
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "wayland-0".to_string())
+}
+
+fn expand(path: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(path).as_ref())
+}
+
+impl RuntimePaths {
+    /// Priority: command line, then config file, then XDG defaults
+    pub fn resolve(config: &PathsConfig, cli: &PathOverrides) -> Self {
+        let runtime = runtime_dir();
+        let state = state_dir();
+        let display = display_suffix();
+
+        let pick = |cli: &Option<PathBuf>, cfg: &Option<String>, default: PathBuf| {
+            cli.clone().or_else(|| cfg.as_deref().map(expand)).unwrap_or(default)
+        };
+
+        let ratatoskr_sockets = match cli.ratatoskr_socket.clone().or_else(|| config.ratatoskr_socket.as_deref().map(expand)) {
+            Some(path) => vec![path],
+            None => vec![runtime.join("ratatoskr.sock"), PathBuf::from(LEGACY_RATATOSKR_SOCKET)],
+        };
+
+        RuntimePaths {
+            command_fifo: pick(&cli.command_fifo, &config.command_fifo, runtime.join(format!("heimdallr-{display}.cmds"))),
+            control_socket: pick(&cli.control_socket, &config.control_socket, runtime.join(format!("heimdallr-{display}.sock"))),
+            ratatoskr_sockets,
+            log_file: pick(&cli.log_file, &config.log_file, state.join("heimdallr.log")),
+            wob_fifo: cli.wob_fifo.clone().or_else(|| config.wob_fifo.as_deref().map(expand)),
+            lock_file: runtime.join(format!("heimdallr-{display}.lock")),
+            history_file: state.join(format!("history-{display}.jsonl")),
+            state_dir: state,
+        }
+    }
+
+    /// Creates the parent directories of everything we are going to create.
+    /// Our own runtime dir must be private, custom locations are the user's business.
+    pub fn prepare(&self) -> std::io::Result<()> {
+        let runtime = runtime_dir();
+        for path in [&self.command_fifo, &self.control_socket, &self.log_file, &self.lock_file].into_iter().chain(self.wob_fifo.as_ref()) {
+            match path.parent() {
+                Some(parent) if parent == runtime => ensure_private_dir(parent)?,
+                Some(parent) => fs::create_dir_all(parent)?,
+                None => {}
+            }
+        }
+        ensure_private_dir(&self.state_dir)?;
+
+        // The history used to be shared by every display, the first instance started takes it over
+        let legacy_history = self.state_dir.join("history.jsonl");
+        if !self.history_file.exists() && legacy_history.exists() {
+            let _ = fs::rename(&legacy_history, &self.history_file);
+        }
+        Ok(())
+    }
+}
@@ -6,11 +6,19 @@ use std::{collections::HashMap, time::{Duration, Instant}};
 use colored::Colorize;
 
 use crate::{
-    countdown::Countdown, data::{AlarmIcon, BatteryDevice, UPowerDeviceKind}, dbg_println, security::MicCameraStatus, utils::{cr_text_layout, ease, get_color_gradient, rounded_rect_gradient, select_icon}
+    countdown::Countdown, data::{AlarmIcon, BatteryDevice, UPowerDeviceKind}, dbg_println, security::MicCameraStatus, markup, config::{LayoutField, NotificationLayout}, utils::{TweenState, cr_markup_layout_lines, cr_text_layout, ease, get_color_gradient, rounded_rect_gradient, select_icon}
 };
 
 pub static PILL_FONT_SIZE: f64 = 14.0;
 pub static PILL_MARGIN: f64 = 6.0;
+/// Largest side of the icon of a notification
+pub static NOTIFICATION_ICON_SIZE: f64 = 48.0;
+/// Space between the app name and the "2/5" counter
+static NOTIFICATION_COUNTER_GAP: f64 = 8.0;
+/// Height of the progress bar of notifications with a value hint
+static NOTIFICATION_PROGRESS_HEIGHT: f64 = 6.0;
+/// The bar needs some room to be readable under a short body
+static NOTIFICATION_PROGRESS_MIN_WIDTH: f64 = 200.0;
 
 pub struct AnimationState {
     current_size: (f64, f64),
@@ -598,7 +606,21 @@ impl PillModuleDevices {
 
 pub struct PillNotificationFull {
     appname_base: PillModuleBase,
+    /// "2/5" after the app name
+    counter_base: PillModuleBase,
+    /// Time received and "5 min ago", also on the first line
+    time_base: PillModuleBase,
+    age_base: PillModuleBase,
+    summary_base: PillModuleBase,
     body_base: PillModuleBase,
+    actions_base: PillModuleBase,
+    icon: Option<ImageSurface>,
+    /// Side of the square the icon is fit in, 0 without icon
+    icon_side: f64,
+    /// Fraction shown by the progress bar, None without value hint
+    progress: Option<TweenState>,
+    /// Notification the progress belongs to, a new one starts the bar from its value instead of animating
+    progress_id: Option<u32>,
     animation: AnimationState,
     // last_notification: Option<crate::notifications::Notification>
 }
@@ -607,34 +629,111 @@ impl PillModuleTrait for PillNotificationFull {
     fn draw(&mut self, cr: &Context, _rect_width: f64, _rect_height: f64, x: f64, y: f64) {
         // self.body_base.draw_centered(&cr, rect_width, rect_height, x, y);
 
-        let x = x + PILL_MARGIN;
+        let x_start = x;
+        let mut x = x + PILL_MARGIN;
         let mut y = y;
 
-        let sizes = self.appname_base.cached_sizes.unwrap_or_default();
-        self.appname_base.draw_centered(cr, sizes.0, sizes.1, x, y);
-        y += sizes.1 + 4.0;
+        if let Some(icon) = &self.icon {
+            let scale = self.icon_side / icon.width().max(icon.height()) as f64;
+            let text_height = self.animation.target_size.1;
+            cr.save().unwrap();
+            cr.translate(
+                x + (self.icon_side - icon.width() as f64 * scale) / 2.0,
+                y + (text_height - icon.height() as f64 * scale) / 2.0
+            );
+            cr.scale(scale, scale);
+            cr.set_source_surface(icon, 0.0, 0.0).unwrap();
+            cr.source().set_filter(cairo::Filter::Good);
+            cr.paint().unwrap();
+            cr.restore().unwrap();
+            x += self.icon_side + PILL_MARGIN;
+        }
+
+        // First line: parts of different sizes, centered vertically
+        let header_height = self.header_height();
+        if header_height > 0.0 {
+            let mut header_x = x;
+            for base in [&self.appname_base, &self.counter_base, &self.time_base, &self.age_base] {
+                if let Some(sizes) = base.cached_sizes {
+                    base.draw_centered(cr, sizes.0, header_height, header_x, y);
+                    header_x += sizes.0 + NOTIFICATION_COUNTER_GAP;
+                }
+            }
+            y += header_height + 4.0;
+        }
+
+        for base in [&self.summary_base, &self.body_base] {
+            if let Some(sizes) = base.cached_sizes {
+                base.draw_centered(cr, sizes.0, sizes.1, x, y);
+                y += sizes.1 + 4.0;
+            }
+        }
+
+        if let Some(progress) = &self.progress {
+            // Under the text column, as wide as the module minus the icon
+            let width = (self.animation.current_size.0 - (x - x_start - PILL_MARGIN)).max(0.0);
+            let r = NOTIFICATION_PROGRESS_HEIGHT / 2.0;
+            rounded_rect_gradient(cr, x, y, width, NOTIFICATION_PROGRESS_HEIGHT, r, vec![(0.0, (1.0, 1.0, 1.0, 0.15))], crate::utils::GradientDirection::Horizontal, false, None);
+            let filled = width * progress.value().clamp(0.0, 1.0);
+            if filled > 0.0 {
+                rounded_rect_gradient(cr, x, y, filled.max(NOTIFICATION_PROGRESS_HEIGHT), NOTIFICATION_PROGRESS_HEIGHT, r, vec![(0.0, (0.35, 0.75, 0.45, 1.0))], crate::utils::GradientDirection::Horizontal, false, None);
+            }
+            y += NOTIFICATION_PROGRESS_HEIGHT + 4.0;
+        }
 
-        let sizes = self.body_base.cached_sizes.unwrap_or_default();
-        self.body_base.draw_centered(cr, sizes.0, sizes.1, x, y);
-        // *y += sizes.1 + 4.0;
+        let sizes = self.actions_base.cached_sizes.unwrap_or_default();
+        self.actions_base.draw_centered(cr, sizes.0, sizes.1, x, y);
     }
 
     fn animation_state(&mut self) -> &mut AnimationState {
         &mut self.animation
     }
+
+    fn step_animation(&mut self) -> bool {
+        let progress_animating = self.progress.as_mut().is_some_and(|p| p.step());
+        self.animation.step() | progress_animating
+    }
 }
 
 impl PillNotificationFull {
     pub fn new() -> Self {
         PillNotificationFull {
             appname_base: PillModuleBase::new(),
+            counter_base: PillModuleBase::new(),
+            time_base: PillModuleBase::new(),
+            age_base: PillModuleBase::new(),
+            summary_base: PillModuleBase::new(),
             body_base: PillModuleBase::new(),
+            actions_base: PillModuleBase::new(),
+            icon: None,
+            icon_side: 0.0,
+            progress: None,
+            progress_id: None,
             animation: AnimationState::new(),
             // last_notification: None
         }
     }
 
-    pub fn update_data(&mut self, cr: &cairo::Context, new_notif: Option<crate::notifications::Notification>) -> bool {
+    fn header_height(&self) -> f64 {
+        [&self.appname_base, &self.counter_base, &self.time_base, &self.age_base].iter()
+            .filter_map(|base| base.cached_sizes)
+            .map(|sizes| sizes.1)
+            .fold(0.0, f64::max)
+    }
+
+    /// Lays out `markup` in `base` if the field is shown and there is something to show; returns the size taken
+    fn set_field(cr: &cairo::Context, base: &mut PillModuleBase, field: &LayoutField, markup: String, max_width: f64, color: (f64, f64, f64, f64)) -> Option<(f64, f64)> {
+        if !field.show || markup.is_empty() {
+            base.clear();
+            return None;
+        }
+        let (layout, sizes) = cr_markup_layout_lines(cr, &markup, field.font_size, max_width, field.max_lines).unwrap();
+        base.set_layout(layout, sizes, markup, color);
+        Some(sizes)
+    }
+
+    /// counter is (position, count) of the notification in the queue
+    pub fn update_data(&mut self, cr: &cairo::Context, new_notif: Option<crate::notifications::Notification>, counter: Option<(usize, usize)>, layout: &NotificationLayout) -> bool {
         // let new_notif = notifications.first().cloned();
         // let changed = self.last_notification != new_notif;
         /* if changed {
@@ -649,33 +748,92 @@ impl PillNotificationFull {
         let target = if let Some(notif) = new_notif {
 
             let white = (1.0, 1.0, 1.0, 1.0);
+            let grey = (0.7, 0.7, 0.7, 1.0);
+            let max_width = layout.max_width;
+
+            // "Slack ×7" when several notifications were collapsed into this one
+            let appname = if notif.count > 1 { format!("{} ×{}", notif.app_name, notif.count) } else { notif.app_name.clone() };
+            // The header parts with their gaps, "Slack ×7  2/5  14:32  5 min ago"
+            let header = [
+                Self::set_field(cr, &mut self.appname_base, &layout.app_name, markup::escape(&appname), max_width, notif.color.unwrap_or(white)),
+                // Follows the app name size, shown even when the app name is hidden
+                Self::set_field(cr, &mut self.counter_base, &LayoutField { show: true, ..layout.app_name.clone() },
+                    counter.map(|(position, count)| format!("{position}/{count}")).unwrap_or_default(), max_width, grey),
+                Self::set_field(cr, &mut self.time_base, &layout.time, markup::escape(&notif.datetime.format(&layout.time_format).to_string()), max_width, grey),
+                Self::set_field(cr, &mut self.age_base, &layout.age, notif.age_text(Local::now()), max_width, grey),
+            ];
+            let header_width = header.iter().flatten().map(|sizes| sizes.0 + NOTIFICATION_COUNTER_GAP).sum::<f64>() - NOTIFICATION_COUNTER_GAP;
+
+            // The body may contain markup, the summary is plain text; with the summary hidden, it stands in for an empty body
+            let body = if !notif.body.is_empty() {
+                markup::to_pango(&notif.body)
+            } else if !layout.summary.show {
+                markup::escape(&notif.summary)
+            } else {
+                String::new()
+            };
+            let body_sizes = Self::set_field(cr, &mut self.body_base, &layout.body, body, max_width, white);
+            // Bold as a title when a body follows it
+            let summary = match (notif.summary.is_empty(), body_sizes.is_some()) {
+                (true, _) => String::new(),
+                (false, true) => format!("<b>{}</b>", markup::escape(&notif.summary)),
+                (false, false) => markup::escape(&notif.summary),
+            };
+            let summary_sizes = Self::set_field(cr, &mut self.summary_base, &layout.summary, summary, max_width, white);
+
+            // Numbered like `notification action <n>` expects them
+            let actions = notif.visible_actions()
+                .enumerate()
+                .map(|(i, (_, label))| format!("{} {}", i + 1, label))
+                .collect::<Vec<_>>()
+                .join("   ");
+
+            let actions_sizes = if actions.is_empty() {
+                self.actions_base.clear();
+                None
+            } else {
+                let (actions_layout, actions_sizes) = cr_text_layout(cr, &actions, PILL_FONT_SIZE - 3.0, Some(max_width)).unwrap();
+                self.actions_base.set_layout(actions_layout, actions_sizes, actions, grey);
+                Some(actions_sizes)
+            };
+            let progress_sizes = match notif.progress {
+                Some(value) => {
+                    let fraction = value as f64 / 100.0;
+                    match (&mut self.progress, self.progress_id == Some(notif.id)) {
+                        (Some(progress), true) => { progress.set_target(fraction); },
+                        _ => self.progress = Some(TweenState::new(fraction)),
+                    }
+                    self.progress_id = Some(notif.id);
+                    Some((NOTIFICATION_PROGRESS_MIN_WIDTH, NOTIFICATION_PROGRESS_HEIGHT))
+                },
+                None => {
+                    self.progress = None;
+                    self.progress_id = None;
+                    None
+                }
+            };
 
-            let (appname_layout, appname_sizes) = cr_text_layout(&cr, &notif.app_name, PILL_FONT_SIZE - 3.0, Some(500.0)).unwrap();
-
-            let target = (appname_sizes.0, appname_sizes.1);
-            
-            self.appname_base.set_layout(appname_layout, target, notif.app_name.to_string(), white);
-            
-            
-            // let datetime = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-            // let (datetime_layout, datetime_sizes) = cr_text_layout(&cr, &datetime, PILL_FONT_SIZE, Some(500.0)).unwrap();
-
-            
-            // let text: &str = "EXAMPLE NOTIFICATION VERY LONG TEXT THAT SHOULD BE BALANCED AND WRAPPED IN THE PILL, BUT IT'S NOT IMPLEMENTED YET. THIS IS JUST A PLACEHOLDER FOR NOW.";
-            let text = if notif.body.is_empty() { notif.summary } else { notif.body };
+            // Lines from top to bottom, 4 pixels apart
+            let header_sizes = (header_width > 0.0).then(|| (header_width, self.header_height()));
+            let lines: Vec<(f64, f64)> = [header_sizes, summary_sizes, body_sizes, progress_sizes, actions_sizes].into_iter().flatten().collect();
+            let text_width = lines.iter().map(|sizes| sizes.0).fold(0.0, f64::max);
+            let text_height = lines.iter().map(|sizes| sizes.1 + 4.0).sum::<f64>() - 4.0;
 
-            let (body_layout, body_sizes) = cr_text_layout(&cr, &text, PILL_FONT_SIZE, Some(500.0)).unwrap();
-            let target = (body_sizes.0, body_sizes.1);
+            // As tall as the text, without growing past NOTIFICATION_ICON_SIZE for long bodies
+            self.icon = notif.icon.as_ref().and_then(|icon| icon.to_surface());
+            self.icon_side = if self.icon.is_some() { text_height.min(NOTIFICATION_ICON_SIZE) } else { 0.0 };
+            let icon_width = if self.icon.is_some() { self.icon_side + PILL_MARGIN } else { 0.0 };
 
-            self.body_base.set_layout(body_layout, target, text.to_string(), white);
             // dbg_println!("{} {target:?}", "Notification target".blue());
-            (
-                appname_sizes.0.max(body_sizes.0),
-                appname_sizes.1 + body_sizes.1 + 4.0
-            )
+            (icon_width + text_width, text_height)
         } else {
-            self.appname_base.clear();
-            self.body_base.clear();
+            for base in [&mut self.appname_base, &mut self.counter_base, &mut self.time_base, &mut self.age_base, &mut self.summary_base, &mut self.body_base, &mut self.actions_base] {
+                base.clear();
+            }
+            self.icon = None;
+            self.icon_side = 0.0;
+            self.progress = None;
+            self.progress_id = None;
             // dbg_println!("{} zero", "Notification target".blue());
             (0.0, 0.0)
         };
@@ -712,6 +870,10 @@ enum PillMode {
 pub struct Pill {
     mode: PillMode,
     last_notification: Option<crate::notifications::Notification>,
+    last_counter: Option<(usize, usize)>,
+    last_layout: Option<NotificationLayout>,
+    /// "5 min ago" of the shown notification, when the layout has it
+    last_age: Option<String>,
     animation: AnimationState,
     // dummy_surface: cairo::ImageSurface,
     dummy_context: cairo::Context,
@@ -780,6 +942,9 @@ impl Pill {
         let dummy_context = Context::new(&dummy_surface).unwrap();
         Pill {
             mode: PillMode::Normal,
+            last_counter: None,
+            last_layout: None,
+            last_age: None,
             last_notification: None,
             animation: AnimationState::new(),
             // dummy_surface,
@@ -819,6 +984,14 @@ impl Pill {
         self.pill_countdown.timer.fill_from_timespan(input) // FIXME: this should be in the pill itself
     }
 
+    pub fn countdown (&self) -> &Countdown {
+        &self.pill_countdown.timer
+    }
+
+    pub fn restore_countdown (&mut self, timer: &crate::handover::TimerHandover) {
+        self.pill_countdown.timer.restore(timer);
+    }
+
     /* pub fn is_countdown_active (&self) -> bool {
         self.pill_countdown.timer.is_active()
     } */
@@ -873,15 +1046,27 @@ impl Pill {
         return changed
     }
     
-    pub fn update_data_notifications(&mut self, notifications: &Vec<crate::notifications::Notification>) -> bool {
+    /// shown is the index of the notification in the pill, None goes back to normal mode
+    pub fn update_data_notifications(&mut self, notifications: &[crate::notifications::Notification], shown: Option<usize>, layout: &NotificationLayout) -> bool {
         // eprintln!("{} {}", "Updating notifications vec, len".red(), notifications.len());
-        let new_notif = notifications.first().cloned();
-        let changed = self.last_notification != new_notif;
+        // Called on every tick, cloned only when something changed
+        let new_notif = shown.and_then(|i| notifications.get(i));
+        // "2/5", only when there is something to browse
+        let counter = shown.filter(|_| notifications.len() > 1).map(|i| (i + 1, notifications.len()));
+        let age = new_notif.filter(|_| layout.age.show).map(|n| n.age_text(Local::now()));
+        let changed = self.last_notification.as_ref() != new_notif
+            || self.last_counter != counter
+            || self.last_layout.as_ref() != Some(layout)
+            || self.last_age != age;
         // eprintln!("{} len {} changed {changed}", "Updating notifications vec".red(), notifications.len());
 
         if changed {
-            let _ = self.pill_notification_full.update_data(&self.dummy_context, new_notif);
-            self.last_notification = notifications.first().cloned();
+            let new_notif = new_notif.cloned();
+            let _ = self.pill_notification_full.update_data(&self.dummy_context, new_notif.clone(), counter, layout);
+            self.last_notification = new_notif;
+            self.last_counter = counter;
+            self.last_layout = Some(layout.clone());
+            self.last_age = age;
 
             if self.last_notification.is_some() {
                 self.mode = PillMode::Notification(self.last_notification.as_ref().unwrap().urgency);
@@ -1,13 +1,14 @@
 use std::process::{Command, Stdio};
 use std::io::BufReader;
 use std::sync::mpsc::Sender;
+use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
 // use colored::Colorize;
 
 use crate::utils::log_to_file;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MicCameraStatus {
     pub mic_active: Vec<String>,
     pub camera_active: Vec<String>,
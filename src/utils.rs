@@ -91,14 +91,26 @@ fn hsv_to_rgb(h: f64, s: f64, v: f64) -> (u8, u8, u8) {
 
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static LOG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Must be called once at startup, messages logged before are dropped
+pub fn set_log_path(path: PathBuf) {
+    let _ = LOG_PATH.set(path);
+}
 
 pub fn log_to_file(msg: String) {
-    let mut file = OpenOptions::new()
+    let Some(path) = LOG_PATH.get() else { return; };
+    let Ok(mut file) = OpenOptions::new()
         .create(true)
         .append(true)
-        .open("/tmp/heimdallr.log")
-        .expect("impossibile aprire log file");
-    writeln!(file, "[{}] {}", chrono::Local::now().format("%H:%M:%S%.3f"), msg).unwrap();
+        .open(path) else {
+        eprintln!("Cannot open log file {}", path.display());
+        return;
+    };
+    let _ = writeln!(file, "[{}] {}", chrono::Local::now().format("%H:%M:%S%.3f"), msg);
 }
 
 
@@ -379,6 +391,22 @@ use cairo::Error;
 } */
 
 pub fn cr_text_layout(cr: &Context, text: &str, font_size: f64, max_width: Option<f64>) -> Result<(pango::Layout, (f64, f64)), Error> {
+    cr_layout(cr, font_size, max_width, |layout| layout.set_text(text))
+}
+
+/// Like cr_text_layout, for Pango markup (see markup::to_pango), ellipsized after max_lines lines (0 for no limit)
+pub fn cr_markup_layout_lines(cr: &Context, markup: &str, font_size: f64, max_width: f64, max_lines: u32) -> Result<(pango::Layout, (f64, f64)), Error> {
+    cr_layout(cr, font_size, Some(max_width), |layout| {
+        layout.set_markup(markup);
+        if max_lines > 0 {
+            layout.set_ellipsize(pango::EllipsizeMode::End);
+            // Negative heights are a number of lines
+            layout.set_height(-(max_lines.min(i32::MAX as u32) as i32));
+        }
+    })
+}
+
+fn cr_layout(cr: &Context, font_size: f64, max_width: Option<f64>, set_content: impl FnOnce(&pango::Layout)) -> Result<(pango::Layout, (f64, f64)), Error> {
 
     let layout = pangocairo::functions::create_layout(cr);
 
@@ -386,7 +414,7 @@ pub fn cr_text_layout(cr: &Context, text: &str, font_size: f64, max_width: Optio
     font_desc.set_family("");
     font_desc.set_absolute_size(font_size * pango::SCALE as f64);
     layout.set_font_description(Some(&font_desc));
-    layout.set_text(text);
+    set_content(&layout);
 
     if let Some(width) = max_width {
         layout.set_width((width * pango::SCALE as f64) as i32);